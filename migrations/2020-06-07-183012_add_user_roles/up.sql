@@ -0,0 +1,4 @@
+-- One of 'user', 'moderator' or 'admin'
+ALTER TABLE `users` ADD COLUMN `role` VARCHAR(16) NOT NULL DEFAULT 'user';
+
+UPDATE `users` SET `role` = 'admin' WHERE `is_admin` = 1;
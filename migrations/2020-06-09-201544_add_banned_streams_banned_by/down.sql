@@ -0,0 +1 @@
+ALTER TABLE `banned_streams` DROP COLUMN `banned_by`;
@@ -0,0 +1,2 @@
+-- ID of the user who banned the stream
+ALTER TABLE `banned_streams` ADD COLUMN `banned_by` CHAR(36);
@@ -0,0 +1,2 @@
+-- 1 if the streaming service marks the stream as mature content
+ALTER TABLE `streams` ADD COLUMN `nsfw` TINYINT(1) NOT NULL DEFAULT 0;
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS `sessions` (
+  -- Session ID, sent as the `jti` claim of the session token
+  `id` CHAR(36) PRIMARY KEY NOT NULL,
+
+  -- The user logged in with this session
+  `user_id` CHAR(36) NOT NULL REFERENCES `users` (`id`) ON DELETE CASCADE,
+
+  -- When the latest token issued for this session expires
+  `expires_at` DATETIME NOT NULL,
+
+  -- When the session was logged out or revoked by an admin
+  `revoked_at` DATETIME,
+
+  -- When the user logged in
+  `created_at` DATETIME NOT NULL,
+
+  -- The last time the session token was refreshed
+  `updated_at` DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS `sessions_user_id` ON `sessions` (`user_id`);
@@ -0,0 +1,3 @@
+-- The previous IDs came from an unstable hash and can't be recomputed, the
+-- rekeyed IDs are kept.
+SELECT 1;
@@ -0,0 +1,33 @@
+-- Stream IDs used to be `DefaultHasher` output masked with `1 << 47`, leaving
+-- only two possible values. Rekey every stream with the 48 bit FNV-1a hash of
+-- "<service>\n<channel>\n<path>" computed by `channel::get_channel_id`, where
+-- path falls back to "/<service>/<channel>".
+--
+-- Only the low 48 bits of the hash are kept, and those only depend on the low
+-- 48 bits of every step, so the hash is computed modulo 2^48 to stay within
+-- SQLite's signed 64 bit integers. Multiplying by the FNV prime, 2^40 + 435,
+-- becomes ((h & 255) << 40) + h * 435, and h XOR c is (h | c) - (h & c).
+UPDATE `streams` SET `id` = (
+  WITH RECURSIVE
+    `input` (`key`) AS (
+      SELECT `streams`.`service` || char(10) || `streams`.`channel` || char(10) ||
+        COALESCE(NULLIF(`streams`.`path`, ''), '/' || `streams`.`service` || '/' || `streams`.`channel`)
+    ),
+    `bytes` (`i`, `c`) AS (
+      SELECT 1, unicode(substr(`key`, 1, 1)) FROM `input`
+      UNION ALL
+      SELECT `i` + 1, unicode(substr(`key`, `i` + 1, 1))
+      FROM `bytes`, `input`
+      WHERE `i` < length(`key`)
+    ),
+    `fnv` (`i`, `hash`) AS (
+      SELECT 0, 172505283306277
+      UNION ALL
+      SELECT `bytes`.`i`, (
+        ((((`hash` | `c`) - (`hash` & `c`)) & 255) << 40)
+        + ((`hash` | `c`) - (`hash` & `c`)) * 435
+      ) & 281474976710655
+      FROM `fnv` JOIN `bytes` ON `bytes`.`i` = `fnv`.`i` + 1
+    )
+  SELECT `hash` FROM `fnv` ORDER BY `i` DESC LIMIT 1
+);
@@ -0,0 +1,4 @@
+-- `role` replaces the admin flag; carry over any admins flagged since it was added
+UPDATE `users` SET `role` = 'admin' WHERE `is_admin` = 1;
+
+ALTER TABLE `users` DROP COLUMN `is_admin`;
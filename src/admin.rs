@@ -0,0 +1,926 @@
+use actix::Addr;
+use actix_web::{web, Error, HttpResponse};
+use chrono::NaiveDateTime;
+
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::channel::{valid_stream_path, Channel};
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::middleware::admin::{Admin, Moderator};
+use crate::models::banned_ip_range::{cidr_bounds, range_bounds, BannedIpRange, IpBanList};
+use crate::models::banned_streams::{self, BannedStream};
+use crate::models::session;
+use crate::models::stream::{self, StreamModeration};
+use crate::models::user::{self, Role, User};
+use crate::registry::{BanStreams, StreamRegistry, StreamRekeyed, StreamUpdated, TerminateUser};
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/banned-streams")
+            .route(web::get().to(get_banned_streams))
+            .route(web::post().to(ban_stream))
+            .route(web::delete().to(unban_stream)),
+    )
+    .service(
+        web::resource("/banned-ip-ranges")
+            .route(web::get().to(get_banned_ip_ranges))
+            .route(web::post().to(ban_ip_range))
+            .route(web::delete().to(unban_ip_range)),
+    )
+    .service(web::resource("/streams/{id}").route(web::patch().to(moderate_stream)))
+    .service(web::resource("/profiles").route(web::get().to(find_user)))
+    .service(web::resource("/profiles/{id}/username").route(web::put().to(rename_user)))
+    .service(
+        web::resource("/profiles/{id}/ban")
+            .route(web::post().to(ban_user))
+            .route(web::delete().to(unban_user)),
+    )
+    .service(web::resource("/profiles/{id}/sessions").route(web::delete().to(terminate_user)));
+}
+
+#[derive(Deserialize)]
+pub struct StreamBan {
+    channel: String,
+    service: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StreamKey {
+    channel: String,
+    service: String,
+}
+
+/// An ip range given as either a `cidr` block or a `start`/`end` pair
+#[derive(Deserialize)]
+pub struct IpRange {
+    cidr: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+impl IpRange {
+    fn bounds(&self) -> Result<(IpAddr, IpAddr), ApiError> {
+        match (&self.cidr, &self.start, &self.end) {
+            (Some(cidr), None, None) => cidr_bounds(cidr),
+            (None, Some(start), Some(end)) => range_bounds(start, end),
+            _ => Err(ApiError::InvalidIpRange(String::from(
+                "expected either a cidr or a start and end",
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IpRangeBan {
+    #[serde(flatten)]
+    range: IpRange,
+    note: Option<String>,
+}
+
+/// Changes to a stream, an empty `path` removes the custom path
+#[derive(Deserialize)]
+pub struct StreamChanges {
+    hidden: Option<bool>,
+    promoted: Option<bool>,
+    path: Option<String>,
+}
+
+impl StreamChanges {
+    fn moderation(self) -> Result<StreamModeration, ApiError> {
+        let path = match self.path {
+            Some(path) if !valid_stream_path(&path) => {
+                return Err(ApiError::ChannelValidation(format!(
+                    "invalid stream path: {}",
+                    path
+                )))
+            }
+            Some(path) if path.is_empty() => Some(None),
+            path => path.map(Some),
+        };
+
+        Ok(StreamModeration {
+            hidden: self.hidden,
+            promoted: self.promoted,
+            path,
+            ..Default::default()
+        })
+    }
+}
+
+async fn get_banned_streams(_: Moderator, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    let all = banned_streams::get_all(&pool)?;
+    Ok(HttpResponse::Ok().json(all))
+}
+
+/// Ban a channel and kick everyone currently watching it
+async fn ban_stream(
+    moderator: Moderator,
+    body: web::Json<StreamBan>,
+    pool: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+) -> Result<HttpResponse, Error> {
+    let body = body.into_inner();
+    let chn = Channel::new(body.channel, body.service, String::new())?;
+
+    let ban = BannedStream {
+        channel: chn.channel,
+        service: chn.service,
+        reason: body.reason,
+        banned_by: Some(moderator.user.id.clone()),
+        ..Default::default()
+    };
+    let ban = banned_streams::insert(&pool, &ban)?;
+    log::info!(
+        "{} banned stream {}/{}: {:?}",
+        moderator.user.name,
+        ban.service,
+        ban.channel,
+        ban.reason
+    );
+
+    let stream_ids = stream::get_by_channel(&pool, &ban.service, &ban.channel)?
+        .into_iter()
+        .filter_map(|s| s.id)
+        .collect();
+    registry.do_send(BanStreams {
+        stream_ids,
+        reason: ban.reason_or_default(),
+    });
+
+    Ok(HttpResponse::Created().json(ban))
+}
+
+async fn unban_stream(
+    moderator: Moderator,
+    query: web::Query<StreamKey>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    let chn = Channel::new(query.channel, query.service, String::new())?;
+
+    let ban = BannedStream {
+        channel: chn.channel,
+        service: chn.service,
+        ..Default::default()
+    };
+    banned_streams::remove(&pool, &ban)?;
+    log::info!(
+        "{} unbanned stream {}/{}",
+        moderator.user.name,
+        ban.service,
+        ban.channel
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Hide, promote or change the path of a stream, pushing the change to viewers
+async fn moderate_stream(
+    moderator: Moderator,
+    stream_id: web::Path<i64>,
+    body: web::Json<StreamChanges>,
+    pool: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+) -> Result<HttpResponse, Error> {
+    let stream_id = stream_id.into_inner();
+    let changes = body.into_inner().moderation()?;
+    if changes.hidden.is_none() && changes.promoted.is_none() && changes.path.is_none() {
+        return Err(ApiError::BadRequest(String::from("nothing to change")).into());
+    }
+
+    let stream = stream::moderate(&pool, stream_id, &changes)?;
+    log::info!(
+        "{} moderated stream {}: hidden={:?} promoted={:?} path={:?}",
+        moderator.user.name,
+        stream_id,
+        stream.hidden,
+        stream.promoted,
+        stream.path
+    );
+    if stream.id == Some(stream_id) {
+        registry.do_send(StreamUpdated { stream_id });
+    } else {
+        registry.do_send(StreamRekeyed {
+            old_id: stream_id,
+            stream: stream.clone(),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(stream))
+}
+
+/// Find a user by exactly one of their `id`, `name` or `twitch_id`
+#[derive(Deserialize)]
+pub struct UserLookup {
+    id: Option<String>,
+    name: Option<String>,
+    twitch_id: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct Rename {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct UserBan {
+    reason: String,
+}
+
+/// A user as shown to moderators, leaving out their address
+#[derive(Serialize)]
+struct ModeratedUser {
+    id: String,
+    twitch_id: i64,
+    name: String,
+    service: String,
+    channel: String,
+    stream_path: String,
+    role: Role,
+    is_banned: bool,
+    ban_reason: Option<String>,
+    last_seen: NaiveDateTime,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+impl From<User> for ModeratedUser {
+    fn from(u: User) -> Self {
+        Self {
+            id: u.id,
+            twitch_id: u.twitch_id,
+            name: u.name,
+            service: u.service,
+            channel: u.channel,
+            stream_path: u.stream_path,
+            role: u.role,
+            is_banned: u.is_banned,
+            ban_reason: u.ban_reason,
+            last_seen: u.last_seen,
+            created_at: u.created_at,
+            updated_at: u.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Terminated {
+    /// number of sessions revoked
+    revoked: usize,
+    /// number of websocket connections closed
+    terminated: usize,
+}
+
+fn get_user(pool: &DbPool, user_id: &str) -> Result<User, ApiError> {
+    let uid = Uuid::parse_str(user_id)
+        .map_err(|_| ApiError::NotFound(format!("failed to find user with id: {}", user_id)))?;
+    user::get_by_id(pool, uid)
+}
+
+/// Load a user the moderator may act on, only users with a lower role qualify
+fn get_moderated_user(pool: &DbPool, moderator: &User, user_id: &str) -> Result<User, ApiError> {
+    let target = get_user(pool, user_id)?;
+    if target.role >= moderator.role {
+        return Err(ApiError::Forbidden(format!(
+            "cannot moderate a user with the {} role",
+            target.role
+        )));
+    }
+    Ok(target)
+}
+
+/// Close every websocket connection of a user, returning how many were open
+async fn terminate_connections(
+    registry: &Addr<StreamRegistry>,
+    user_id: &str,
+    reason: String,
+) -> Result<usize, ApiError> {
+    registry
+        .send(TerminateUser {
+            user_id: user_id.to_string(),
+            reason,
+        })
+        .await
+        .map_err(|e| {
+            ApiError::Internal(format!(
+                "failed to terminate connections of {}: {}",
+                user_id, e
+            ))
+        })
+}
+
+async fn find_user(
+    _: Moderator,
+    query: web::Query<UserLookup>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let found = match &*query {
+        UserLookup {
+            id: Some(id),
+            name: None,
+            twitch_id: None,
+        } => get_user(&pool, id),
+        UserLookup {
+            id: None,
+            name: Some(name),
+            twitch_id: None,
+        } => user::get_by_name(&pool, name),
+        UserLookup {
+            id: None,
+            name: None,
+            twitch_id: Some(twitch_id),
+        } => user::get_by_twitch_id(&pool, *twitch_id),
+        _ => {
+            return Err(ApiError::BadRequest(String::from(
+                "expected exactly one of id, name or twitch_id",
+            ))
+            .into())
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(ModeratedUser::from(found?)))
+}
+
+async fn rename_user(
+    admin: Admin,
+    user_id: web::Path<String>,
+    body: web::Json<Rename>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let target = get_moderated_user(&pool, &admin.user, &user_id)?;
+    let renamed = user::rename(&pool, &target.id, &body.name)?;
+    log::info!(
+        "{} renamed user {} from {} to {}",
+        admin.user.name,
+        renamed.id,
+        target.name,
+        renamed.name
+    );
+
+    Ok(HttpResponse::Ok().json(ModeratedUser::from(renamed)))
+}
+
+/// Ban a user, revoking their sessions and closing their open connections
+async fn ban_user(
+    moderator: Moderator,
+    user_id: web::Path<String>,
+    body: web::Json<UserBan>,
+    pool: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+) -> Result<HttpResponse, Error> {
+    let target = get_moderated_user(&pool, &moderator.user, &user_id)?;
+    let banned = user::set_banned(&pool, &target.id, Some(&body.reason))?;
+    let revoked = session::revoke_all_for_user(&pool, &banned.id)?;
+    let terminated =
+        terminate_connections(&registry, &banned.id, format!("banned: {}", body.reason)).await?;
+    log::info!(
+        "{} banned user {} ({} sessions revoked, {} connections closed): {}",
+        moderator.user.name,
+        banned.name,
+        revoked,
+        terminated,
+        body.reason
+    );
+
+    Ok(HttpResponse::Ok().json(ModeratedUser::from(banned)))
+}
+
+async fn unban_user(
+    moderator: Moderator,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let target = get_moderated_user(&pool, &moderator.user, &user_id)?;
+    let unbanned = user::set_banned(&pool, &target.id, None)?;
+    log::info!("{} unbanned user {}", moderator.user.name, unbanned.name);
+
+    Ok(HttpResponse::Ok().json(ModeratedUser::from(unbanned)))
+}
+
+/// Revoke every session of a user and close their open connections,
+/// without banning them
+async fn terminate_user(
+    moderator: Moderator,
+    user_id: web::Path<String>,
+    pool: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+) -> Result<HttpResponse, Error> {
+    let target = get_moderated_user(&pool, &moderator.user, &user_id)?;
+    let revoked = session::revoke_all_for_user(&pool, &target.id)?;
+    let terminated = terminate_connections(
+        &registry,
+        &target.id,
+        String::from("session terminated by a moderator"),
+    )
+    .await?;
+    log::info!(
+        "{} revoked {} sessions and closed {} connections of user {}",
+        moderator.user.name,
+        revoked,
+        terminated,
+        target.name
+    );
+
+    Ok(HttpResponse::Ok().json(Terminated {
+        revoked,
+        terminated,
+    }))
+}
+
+async fn get_banned_ip_ranges(
+    _: Moderator,
+    bans: web::Data<IpBanList>,
+) -> Result<HttpResponse, Error> {
+    let all = bans.get_all()?;
+    Ok(HttpResponse::Ok().json(all))
+}
+
+/// Ban a range of addresses, responding with the range in canonical form
+async fn ban_ip_range(
+    moderator: Moderator,
+    body: web::Json<IpRangeBan>,
+    bans: web::Data<IpBanList>,
+) -> Result<HttpResponse, Error> {
+    let (start, end) = body.range.bounds()?;
+
+    let range = BannedIpRange {
+        start: start.to_string(),
+        end: end.to_string(),
+        note: body.note.clone(),
+        ..Default::default()
+    };
+    let range = bans.insert(&range)?;
+    log::info!(
+        "{} banned ip range {} - {}: {:?}",
+        moderator.user.name,
+        range.start,
+        range.end,
+        range.note
+    );
+
+    Ok(HttpResponse::Created().json(range))
+}
+
+async fn unban_ip_range(
+    moderator: Moderator,
+    query: web::Query<IpRange>,
+    bans: web::Data<IpBanList>,
+) -> Result<HttpResponse, Error> {
+    let (start, end) = query.bounds()?;
+
+    let range = BannedIpRange {
+        start: start.to_string(),
+        end: end.to_string(),
+        ..Default::default()
+    };
+    bans.remove(&range)?;
+    log::info!(
+        "{} unbanned ip range {} - {}",
+        moderator.user.name,
+        range.start,
+        range.end
+    );
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::middleware::auth::{session_cookie, start_session};
+    use crate::routes::routes;
+    use actix::Actor;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::{json, Value};
+
+    fn authed(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.cookie(session_cookie(token.to_string()))
+    }
+
+    async fn read_json(resp: ServiceResponse) -> Value {
+        serde_json::from_slice(&test::read_body(resp).await).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn it_bans_lists_and_unbans_a_stream() {
+        let pool = setup_pool();
+        let (moderator, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let ban = json!({"channel": "jbpratt", "service": "twitch", "reason": "dmca"});
+        let req = test::TestRequest::post()
+            .uri("/api/admin/banned-streams")
+            .set_json(&ban);
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let created: Value = read_json(resp).await;
+        assert_eq!(created["banned_by"], json!(moderator.id));
+
+        let req = test::TestRequest::post()
+            .uri("/api/admin/banned-streams")
+            .set_json(&ban);
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let req = test::TestRequest::get().uri("/api/admin/banned-streams");
+        let all: Value =
+            read_json(test::call_service(&mut app, authed(req, &token).to_request()).await).await;
+        assert_eq!(all.as_array().unwrap().len(), 1);
+        assert_eq!(all[0]["reason"], "dmca");
+
+        let uri = "/api/admin/banned-streams?channel=jbpratt&service=twitch";
+        let resp = test::call_service(
+            &mut app,
+            authed(test::TestRequest::delete().uri(uri), &token).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = test::call_service(
+            &mut app,
+            authed(test::TestRequest::delete().uri(uri), &token).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_invalid_stream_bans() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let (_, viewer) = setup_session(&pool, "viewer", Role::User);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/admin/banned-streams")
+            .set_json(&json!({"channel": "jbpratt", "service": "twitter"}));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get().uri("/api/admin/banned-streams");
+        let resp = test::call_service(&mut app, authed(req, &viewer).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn it_bans_and_unbans_ip_ranges() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let ban_list = IpBanList::load(pool.clone()).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(pool)
+                .data(ban_list.clone())
+                .configure(routes),
+        )
+        .await;
+
+        let bans = [
+            json!({"cidr": "10.0.0.0/8", "note": "vpn"}),
+            json!({"start": "2001:0db8::0001", "end": "2001:db8::00ff"}),
+        ];
+        for ban in bans.iter() {
+            let req = test::TestRequest::post()
+                .uri("/api/admin/banned-ip-ranges")
+                .set_json(ban);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        let req = test::TestRequest::get().uri("/api/admin/banned-ip-ranges");
+        let all: Value =
+            read_json(test::call_service(&mut app, authed(req, &token).to_request()).await).await;
+        assert_eq!(all[0]["start"], "10.0.0.0");
+        assert_eq!(all[0]["end"], "10.255.255.255");
+        assert_eq!(all[0]["note"], "vpn");
+        assert_eq!(all[1]["start"], "2001:db8::1");
+        assert_eq!(all[1]["end"], "2001:db8::ff");
+        assert!(ban_list.check("10.20.30.40".parse().unwrap()).is_some());
+
+        for uri in &[
+            "/api/admin/banned-ip-ranges?cidr=10.0.0.0/8",
+            "/api/admin/banned-ip-ranges?start=2001:db8::1&end=2001:db8::ff",
+        ] {
+            let req = test::TestRequest::delete().uri(uri);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT, "{}", uri);
+        }
+        assert!(ban_list.check("10.20.30.40".parse().unwrap()).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_invalid_ip_ranges() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let ban_list = IpBanList::load(pool.clone()).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(pool)
+                .data(ban_list.clone())
+                .configure(routes),
+        )
+        .await;
+
+        let bans = [
+            json!({"start": "10.0.0.9", "end": "10.0.0.1"}),
+            json!({"start": "10.0.0.1", "end": "::1"}),
+            json!({"cidr": "10.0.0.0/40"}),
+            json!({"cidr": "10.0.0.0/8", "start": "10.0.0.1", "end": "10.0.0.2"}),
+            json!({"start": "10.0.0.1"}),
+        ];
+        for ban in bans.iter() {
+            let req = test::TestRequest::post()
+                .uri("/api/admin/banned-ip-ranges")
+                .set_json(ban);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", ban);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_hides_promotes_and_moves_a_stream() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let stream = stream::Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+        let stream_id = stream::insert(&pool, stream).unwrap().id.unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(pool.clone())
+                .data(registry)
+                .configure(routes),
+        )
+        .await;
+
+        let changes = [
+            (json!({"hidden": true, "promoted": true}), json!(null)),
+            (json!({"path": "jbpratt_irl"}), json!("jbpratt_irl")),
+            (json!({"hidden": false, "path": ""}), json!(null)),
+        ];
+        let mut moved_id = stream_id;
+        for (change, path) in changes.iter() {
+            let uri = format!("/api/admin/streams/{}", moved_id);
+            let req = test::TestRequest::patch().uri(&uri).set_json(change);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", change);
+            let moderated = read_json(resp).await;
+            assert_eq!(moderated["path"], *path, "{}", change);
+            moved_id = moderated["id"].as_i64().unwrap();
+        }
+
+        // clearing the path moves the stream back to the channel's own id
+        assert_eq!(moved_id, stream_id);
+        let found = stream::get_by_id(&pool, stream_id).unwrap();
+        assert_eq!(found.hidden, Some(false));
+        assert_eq!(found.promoted, Some(true));
+        assert_eq!(found.path, None);
+    }
+
+    #[actix_rt::test]
+    async fn it_refuses_to_move_a_stream_to_a_taken_path() {
+        let pool = setup_pool();
+        let (mut owner, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        owner.stream_path = String::from("moderator");
+        user::update(&pool, &owner).unwrap();
+        let stream = stream::Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+        let stream_id = stream::insert(&pool, stream).unwrap().id.unwrap();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let uri = format!("/api/admin/streams/{}", stream_id);
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(&json!({"path": "moderator"}));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body = read_json(resp).await;
+        assert_eq!(body["error"]["code"], "stream_path_taken");
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_invalid_stream_changes() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let stream = stream::Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+        let stream_id = stream::insert(&pool, stream).unwrap().id.unwrap();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let uri = format!("/api/admin/streams/{}", stream_id);
+        let cases = [
+            (uri.as_str(), json!({}), StatusCode::BAD_REQUEST),
+            (
+                uri.as_str(),
+                json!({"path": "No Spaces"}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/api/admin/streams/1",
+                json!({"hidden": true}),
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+        for (uri, change, expected) in cases.iter() {
+            let req = test::TestRequest::patch().uri(uri).set_json(change);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), *expected, "{} {}", uri, change);
+        }
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(&json!({"hidden": true}));
+        let resp = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_finds_users_by_id_name_or_twitch_id() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let (target, _) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool).configure(routes)).await;
+
+        for query in &[
+            format!("id={}", target.id),
+            String::from("name=rustler"),
+            format!("twitch_id={}", target.twitch_id),
+        ] {
+            let req = test::TestRequest::get().uri(&format!("/api/admin/profiles?{}", query));
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", query);
+            assert_eq!(read_json(resp).await["id"], json!(target.id), "{}", query);
+        }
+
+        for (query, expected) in &[
+            ("name=nobody", StatusCode::NOT_FOUND),
+            ("id=not-a-uuid", StatusCode::NOT_FOUND),
+            ("", StatusCode::BAD_REQUEST),
+            ("name=rustler&twitch_id=1", StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::get().uri(&format!("/api/admin/profiles?{}", query));
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), *expected, "{}", query);
+            if *expected == StatusCode::NOT_FOUND {
+                assert_eq!(read_json(resp).await["error"]["code"], "not_found");
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_renames_users_as_an_admin() {
+        let pool = setup_pool();
+        let (_, moderator) = setup_session(&pool, "moderator", Role::Moderator);
+        let (_, admin) = setup_session(&pool, "admin", Role::Admin);
+        let (target, _) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+        let uri = format!("/api/admin/profiles/{}/username", target.id);
+
+        let cases = [
+            (
+                &moderator,
+                json!({"name": "renamed"}),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                &admin,
+                json!({"name": "not valid"}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (&admin, json!({"name": "moderator"}), StatusCode::CONFLICT),
+            (&admin, json!({"name": "renamed"}), StatusCode::OK),
+        ];
+        for (token, body, expected) in cases.iter() {
+            let req = test::TestRequest::put().uri(&uri).set_json(body);
+            let resp = test::call_service(&mut app, authed(req, token).to_request()).await;
+            assert_eq!(resp.status(), *expected, "{}", body);
+        }
+
+        assert_eq!(user::get_by_name(&pool, "renamed").unwrap().id, target.id);
+    }
+
+    #[actix_rt::test]
+    async fn it_bans_and_unbans_a_user() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let (target, target_token) = setup_session(&pool, "rustler", Role::User);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app = test::init_service(
+            App::new()
+                .data(pool.clone())
+                .data(registry)
+                .configure(routes),
+        )
+        .await;
+        let uri = format!("/api/admin/profiles/{}/ban", target.id);
+
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .set_json(&json!({"reason": "spam"}));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let banned = read_json(resp).await;
+        assert_eq!(banned["is_banned"], true);
+        assert_eq!(banned["ban_reason"], "spam");
+        assert!(banned.get("last_ip").is_none());
+
+        // the ban revoked the user's session, a new login is refused
+        let new_token = start_session(&pool, &target.id).unwrap();
+        for (session, expected) in &[
+            (&target_token, StatusCode::UNAUTHORIZED),
+            (&new_token, StatusCode::FORBIDDEN),
+        ] {
+            let req = test::TestRequest::get().uri("/api/profile");
+            let resp = test::call_service(&mut app, authed(req, session).to_request()).await;
+            assert_eq!(resp.status(), *expected);
+        }
+
+        let req = test::TestRequest::delete().uri(&uri);
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // unbanning doesn't bring the revoked session back
+        for (session, expected) in &[
+            (&target_token, StatusCode::UNAUTHORIZED),
+            (&new_token, StatusCode::OK),
+        ] {
+            let req = test::TestRequest::get().uri("/api/profile");
+            let resp = test::call_service(&mut app, authed(req, session).to_request()).await;
+            assert_eq!(resp.status(), *expected);
+        }
+        assert!(!user::get_by_name(&pool, "rustler").unwrap().is_banned);
+    }
+
+    #[actix_rt::test]
+    async fn it_revokes_every_session_of_a_user() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let (target, target_token) = setup_session(&pool, "rustler", Role::User);
+        start_session(&pool, &target.id).unwrap();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let req =
+            test::TestRequest::delete().uri(&format!("/api/admin/profiles/{}/sessions", target.id));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            read_json(resp).await,
+            json!({"revoked": 2, "terminated": 0})
+        );
+
+        let req = test::TestRequest::get().uri("/api/profile");
+        let resp = test::call_service(&mut app, authed(req, &target_token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // the moderator's own session is untouched
+        let req = test::TestRequest::get().uri("/api/profile");
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn it_only_moderates_users_with_a_lower_role() {
+        let pool = setup_pool();
+        let (moderator, token) = setup_session(&pool, "moderator", Role::Moderator);
+        let (admin, _) = setup_session(&pool, "admin", Role::Admin);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        for target in &[&moderator, &admin] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/api/admin/profiles/{}/ban", target.id))
+                .set_json(&json!({"reason": "mutiny"}));
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", target.name);
+
+            let req = test::TestRequest::delete()
+                .uri(&format!("/api/admin/profiles/{}/sessions", target.id));
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", target.name);
+        }
+    }
+}
@@ -1,24 +1,8 @@
 use regex::Regex;
 use url::Url;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-
 use crate::errors::ApiError;
-
-const SERVICES: [&str; 11] = [
-    "advanced",
-    "angelthump",
-    "facebook",
-    "m3u8",
-    "smashcast",
-    "twitch",
-    "twitch-vod",
-    "ustream",
-    "vaughn",
-    "youtube",
-    "youtube-playlist",
-];
+use crate::services::SERVICES;
 
 #[derive(Debug, PartialEq, Clone, Hash)]
 pub struct Channel {
@@ -41,10 +25,7 @@ impl Channel {
             )));
         }
         // normalize channel
-        let normalized_channel = match normalize_channel(service.as_str(), channel.as_str()) {
-            Ok(chn) => chn,
-            Err(e) => return Err(e),
-        };
+        let normalized_channel = normalize_channel(service.as_str(), channel.as_str())?;
 
         // validate path
         if !valid_stream_path(stream_path.as_str()) {
@@ -73,21 +54,34 @@ impl Channel {
         }
     }
 }
-pub fn get_channel_id(chn: &Channel) -> u64 {
-    &calc_channel_hash(chn) & 1 << (48 - 1)
-}
 
-fn calc_channel_hash(chn: &Channel) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    chn.hash(&mut hasher);
-    hasher.finish()
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+/// Stream ids are kept to 48 bits so they stay exact as javascript numbers
+const CHANNEL_ID_MASK: u64 = (1 << 48) - 1;
+
+/// Stable id of a channel's stream: the 64 bit FNV-1a hash of
+/// `"<service>\n<channel>\n<stream_path>"`, truncated to its low 48 bits.
+///
+/// Stream ids are persisted, so this must never change without a migration
+/// rekeying the `streams` table, see `migrations/*_rekey_streams`.
+pub fn get_channel_id(chn: &Channel) -> u64 {
+    let key = [&chn.service, &chn.channel, &chn.stream_path];
+    let bytes = key.iter().enumerate().flat_map(|(i, part)| {
+        let separator = if i > 0 { &b"\n"[..] } else { &[] };
+        separator.iter().chain(part.as_bytes())
+    });
+
+    bytes.fold(FNV_OFFSET_BASIS, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME)
+    }) & CHANNEL_ID_MASK
 }
 
 pub fn valid_service(service: &str) -> bool {
-    SERVICES.contains(&service)
+    SERVICES.iter().any(|(name, _)| *name == service)
 }
 
-fn valid_stream_path(path: &str) -> bool {
+pub fn valid_stream_path(path: &str) -> bool {
     lazy_static! {
         static ref RE: Regex = Regex::new(r"^[a-z0-9_]{3,32}$").unwrap();
     }
@@ -105,7 +99,7 @@ fn valid_basic_channel(channel: &str) -> bool {
     RE.is_match(channel)
 }
 
-fn normalize_channel(service: &str, channel: &str) -> anyhow::Result<String, ApiError> {
+pub fn normalize_channel(service: &str, channel: &str) -> Result<String, ApiError> {
     // advanced
     if service == "advanced" || service == "m3u8" {
         let channel_uri = Url::parse(channel)?;
@@ -130,6 +124,8 @@ fn normalize_channel(service: &str, channel: &str) -> anyhow::Result<String, Api
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
+
     #[test]
     fn it_creates_a_valid_channel() {
         let response = Channel::new(
@@ -196,6 +192,30 @@ mod tests {
         .unwrap();
 
         let chn_hash = get_channel_id(&response);
-        assert_eq!(chn_hash, 140737488355328);
+        assert_eq!(chn_hash, 109058612824315);
+    }
+
+    #[test]
+    fn it_hashes_channels_to_distinct_48_bit_ids() {
+        let mut ids = HashSet::new();
+        for service in &["twitch", "youtube", "angelthump"] {
+            for i in 0..1000 {
+                let chn =
+                    Channel::new(format!("channel_{}", i), service.to_string(), String::new())
+                        .unwrap();
+                let id = get_channel_id(&chn);
+                assert!(id < 1 << 48);
+                ids.insert(id);
+            }
+        }
+        assert_eq!(ids.len(), 3000);
+
+        let with_path = Channel::new(
+            String::from("channel_0"),
+            String::from("twitch"),
+            String::from("custom"),
+        )
+        .unwrap();
+        assert!(!ids.contains(&get_channel_id(&with_path)));
     }
 }
@@ -1,4 +1,5 @@
 use std::env;
+use std::net::IpAddr;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
@@ -7,8 +8,10 @@ pub struct Config {
     pub twitch_client_id: String,
     pub twitch_client_secret: String,
     pub twitch_redirect_url: String,
+    pub twitch_token_url: String,
     pub jwt_key: String,
     pub jwt_ttl: i64,
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 lazy_static! {
@@ -26,11 +29,21 @@ fn get_config() -> Config {
         env::var("TWITCH_CLIENT_ID").expect("`TWITCH_CLIENT_ID` set for authorization");
     let twitch_redirect_url =
         env::var("TWITCH_REDIRECT_URL").expect("`TWITCH_REDIRECT_URL` set for authorization");
+    let twitch_token_url = env::var("TWITCH_TOKEN_URL")
+        .unwrap_or_else(|_| String::from("https://id.twitch.tv/oauth2/token"));
     let jwt_key = env::var("JWT_KEY").expect("JWT_KEY");
     let jwt_ttl = env::var("JWT_TTL")
         .expect("JWT_TTL")
         .parse::<i64>()
         .expect("i64 for JWT_TTL");
+    // proxies whose `X-Forwarded-For` header is trusted, comma separated
+    let trusted_proxies = env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| addr.parse().expect("ip addresses for TRUSTED_PROXIES"))
+        .collect();
 
     Config {
         database_url,
@@ -38,8 +51,10 @@ fn get_config() -> Config {
         twitch_client_id,
         twitch_client_secret,
         twitch_redirect_url,
+        twitch_token_url,
         jwt_key,
         jwt_ttl,
+        trusted_proxies,
     }
 }
 
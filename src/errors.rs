@@ -1,5 +1,6 @@
-#![allow(dead_code)]
-use diesel::{r2d2, result::Error as DBError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use diesel::r2d2;
+use diesel::result::{DatabaseErrorKind, Error as DBError};
 use thiserror::Error;
 
 use std::fmt;
@@ -26,8 +27,151 @@ pub enum ApiError {
     CannotParseUuid(String),
     #[error("failed to parse ipaddr: {0}")]
     CannotParseIPAddr(String),
+    #[error("invalid ip range: {0}")]
+    InvalidIpRange(String),
     #[error("invalid service: {0}")]
     InvalidService(String),
+    #[error("invalid role: {0}")]
+    InvalidRole(String),
+    #[error("invalid name: {0}")]
+    InvalidName(String),
+    #[error("stream is banned: {0}")]
+    StreamBanned(String),
+    #[error("service request failed: {0}")]
+    ServiceRequest(String),
+    #[error("stream id collision: {0}")]
+    StreamIdCollision(String),
+    #[error("stream path is taken: {0}")]
+    StreamPathTaken(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl ApiError {
+    /// Stable identifier of the error kind, for clients to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::DatabaseError(DBError::NotFound) => "not_found",
+            ApiError::DatabaseError(DBError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => "conflict",
+            ApiError::DatabaseError(_) => "database_error",
+            ApiError::PoolError(_) => "pool_error",
+            ApiError::CannotEncodeSessionToken(_) => "session_encoding",
+            ApiError::CannotDecodeSessionToken(_) => "invalid_session",
+            ApiError::SchemaValidation(_) => "schema_validation",
+            ApiError::ChannelValidation(_) => "channel_validation",
+            ApiError::ChannelNormalization(_) => "channel_normalization",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::CannotParseUuid(_) => "invalid_uuid",
+            ApiError::CannotParseIPAddr(_) => "invalid_ip_address",
+            ApiError::InvalidIpRange(_) => "invalid_ip_range",
+            ApiError::InvalidService(_) => "invalid_service",
+            ApiError::InvalidRole(_) => "invalid_role",
+            ApiError::InvalidName(_) => "invalid_name",
+            ApiError::StreamBanned(_) => "stream_banned",
+            ApiError::ServiceRequest(_) => "service_request",
+            ApiError::StreamIdCollision(_) => "stream_id_collision",
+            ApiError::StreamPathTaken(_) => "stream_path_taken",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Message safe to show to clients, internals are hidden for 5xx errors
+    pub fn message(&self) -> String {
+        let status = self.status_code();
+        if status.is_server_error() {
+            return status
+                .canonical_reason()
+                .unwrap_or("internal error")
+                .to_lowercase();
+        }
+
+        match self {
+            ApiError::NotFound(msg) | ApiError::StreamBanned(msg) => msg.clone(),
+            ApiError::DatabaseError(DBError::NotFound) => String::from("not found"),
+            ApiError::DatabaseError(_) => String::from("already exists"),
+            e => e.to_string(),
+        }
+    }
+}
+
+/// Body of every error response, `{"error": {"code": .., "message": ..}}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::DatabaseError(DBError::NotFound) | ApiError::NotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::DatabaseError(DBError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _,
+            ))
+            | ApiError::StreamIdCollision(_)
+            | ApiError::StreamPathTaken(_) => StatusCode::CONFLICT,
+            ApiError::DatabaseError(_)
+            | ApiError::PoolError(_)
+            | ApiError::CannotEncodeSessionToken(_)
+            | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::SchemaValidation(_) | ApiError::ServiceRequest(_) => StatusCode::BAD_GATEWAY,
+            ApiError::CannotDecodeSessionToken(_) | ApiError::Unauthorized(_) => {
+                StatusCode::UNAUTHORIZED
+            }
+            ApiError::StreamBanned(_) | ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::ChannelValidation(_)
+            | ApiError::ChannelNormalization(_)
+            | ApiError::CannotParseUuid(_)
+            | ApiError::CannotParseIPAddr(_)
+            | ApiError::InvalidIpRange(_)
+            | ApiError::InvalidService(_)
+            | ApiError::InvalidRole(_)
+            | ApiError::InvalidName(_)
+            | ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            log::error!("request failed: {}", self);
+        }
+
+        HttpResponse::build(status).json(ErrorResponse {
+            error: ErrorBody {
+                code: self.code().to_string(),
+                message: self.message(),
+            },
+        })
+    }
+}
+
+/// Errors from service clients, keeping any `ApiError` they wrap
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        e.downcast::<ApiError>()
+            .unwrap_or_else(|e| ApiError::ServiceRequest(e.to_string()))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -49,3 +193,108 @@ impl From<r2d2::PoolError> for ApiError {
         ApiError::PoolError(PoolError(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::{Body, ResponseBody};
+
+    fn render(e: ApiError) -> (StatusCode, ErrorResponse) {
+        let resp = e.error_response();
+        let body = match resp.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => bytes.clone(),
+            _ => panic!("expected a json body"),
+        };
+        (resp.status(), serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn it_renders_errors_as_a_json_envelope() {
+        let cases = vec![
+            (
+                ApiError::NotFound(String::from("no such stream")),
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "no such stream",
+            ),
+            (
+                ApiError::InvalidService(String::from("chaturbate")),
+                StatusCode::BAD_REQUEST,
+                "invalid_service",
+                "invalid service: chaturbate",
+            ),
+            (
+                ApiError::StreamBanned(String::from("spam")),
+                StatusCode::FORBIDDEN,
+                "stream_banned",
+                "spam",
+            ),
+            (
+                ApiError::CannotDecodeSessionToken(String::from("expired")),
+                StatusCode::UNAUTHORIZED,
+                "invalid_session",
+                "failed to decode session token: expired",
+            ),
+            (
+                ApiError::Forbidden(String::from("requires the admin role")),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "requires the admin role",
+            ),
+            (
+                ApiError::DatabaseError(DBError::NotFound),
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "not found",
+            ),
+        ];
+        for (e, status, code, message) in cases {
+            let (found, body) = render(e);
+            assert_eq!(found, status, "{}", code);
+            assert_eq!(body.error.code, code);
+            assert_eq!(body.error.message, message, "{}", code);
+        }
+    }
+
+    #[test]
+    fn it_hides_internal_errors() {
+        let cases = vec![
+            (
+                ApiError::DatabaseError(DBError::RollbackTransaction),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                "internal server error",
+            ),
+            (
+                ApiError::SchemaValidation(String::from("missing field `viewers`")),
+                StatusCode::BAD_GATEWAY,
+                "schema_validation",
+                "bad gateway",
+            ),
+            (
+                ApiError::Internal(String::from("mailbox closed")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                "internal server error",
+            ),
+        ];
+        for (e, status, code, message) in cases {
+            let (found, body) = render(e);
+            assert_eq!(found, status);
+            assert_eq!(body.error.code, code);
+            assert_eq!(body.error.message, message);
+        }
+    }
+
+    #[test]
+    fn it_keeps_api_errors_from_service_clients() {
+        let e = anyhow::Error::new(ApiError::InvalidName(String::from("x")));
+        assert_eq!(ApiError::from(e), ApiError::InvalidName(String::from("x")));
+
+        let e = anyhow::anyhow!("connection refused");
+        assert_eq!(
+            ApiError::from(e),
+            ApiError::ServiceRequest(String::from("connection refused"))
+        );
+    }
+}
@@ -2,24 +2,12 @@ use actix_web::{
     body::Body,
     web::{HttpResponse, Json},
 };
-use diesel::r2d2::{self, ConnectionManager};
-use diesel::SqliteConnection;
-use lazy_static::lazy_static;
 use serde::Serialize;
 
-use crate::database::DbPool;
 use crate::errors::ApiError;
 
-pub fn setup_pool() -> DbPool {
-    let manager = ConnectionManager::<SqliteConnection>::new(":memory:");
-    let pool: DbPool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("failed to create pool");
-
-    embed_migrations!();
-    embedded_migrations::run(&pool.get().unwrap()).expect("failed to run migrations");
-    pool
-}
+#[cfg(test)]
+pub use self::fixtures::{setup_pool, setup_session};
 
 pub fn respond_json<T>(data: T) -> anyhow::Result<Json<T>, ApiError>
 where
@@ -28,10 +16,52 @@ where
     Ok(Json(data))
 }
 
+#[allow(dead_code)]
 pub fn respond_ok() -> anyhow::Result<HttpResponse, ApiError> {
     Ok(HttpResponse::Ok().body(Body::Empty))
 }
 
+/// Databases and sessions shared by tests across modules
+#[cfg(test)]
+mod fixtures {
+    use diesel::r2d2::{self, ConnectionManager};
+    use diesel::SqliteConnection;
+
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use crate::channel::Channel;
+    use crate::database::DbPool;
+    use crate::middleware::auth::start_session;
+    use crate::models::user::{self, Role};
+
+    pub fn setup_pool() -> DbPool {
+        let manager = ConnectionManager::<SqliteConnection>::new(":memory:");
+        // every connection to ":memory:" is its own database, so keep a single one
+        let pool: DbPool = r2d2::Pool::builder()
+            .max_size(1)
+            .build(manager)
+            .expect("failed to create pool");
+
+        embed_migrations!();
+        embedded_migrations::run(&pool.get().unwrap()).expect("failed to run migrations");
+        pool
+    }
+
+    /// Create a user with the given role, returning a session token for them
+    pub fn setup_session(pool: &DbPool, name: &str, role: Role) -> (user::User, String) {
+        static TWITCH_IDS: AtomicI64 = AtomicI64::new(1);
+
+        let chn = Channel::new(name.to_string(), String::from("twitch"), String::new()).unwrap();
+        let twitch_id = TWITCH_IDS.fetch_add(1, Ordering::SeqCst);
+        let mut u = user::create(pool, twitch_id, chn, name, "0.0.0.0").unwrap();
+        u.role = role;
+        user::update(pool, &u).unwrap();
+
+        let token = start_session(pool, &u.id).unwrap();
+        (u, token)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -4,22 +4,30 @@ extern crate diesel;
 extern crate serde;
 #[macro_use]
 extern crate lazy_static;
-#[macro_use]
+#[cfg_attr(test, macro_use)]
 extern crate diesel_migrations;
 extern crate thiserror;
 
+mod admin;
 mod channel;
 mod config;
 mod database;
 mod errors;
 mod helpers;
 mod middleware;
+// diesel 1.4 derives and `table!` expand to impls nested in constants
+#[allow(non_local_definitions)]
 mod models;
+mod poller;
+mod profile;
+mod protocol;
+mod registry;
 mod routes;
+#[allow(non_local_definitions)]
 mod schema;
 mod server;
 mod state;
-mod stream;
+mod streams;
 mod wsservice;
 
 mod service;
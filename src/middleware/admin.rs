@@ -0,0 +1,110 @@
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+
+use crate::errors::ApiError;
+use crate::middleware::auth::authenticate;
+use crate::models::user::{Role, User};
+
+/// A logged in admin. Anonymous requests get 401, other users 403.
+#[derive(Debug, Clone)]
+pub struct Admin {
+    pub user: User,
+}
+
+/// A logged in moderator or admin. Anonymous requests get 401, other users 403.
+#[derive(Debug, Clone)]
+pub struct Moderator {
+    pub user: User,
+}
+
+fn require_role(req: &HttpRequest, role: Role) -> Result<User, ApiError> {
+    let user = authenticate(req)?.user;
+    if user.role < role {
+        return Err(ApiError::Forbidden(format!("requires the {} role", role)));
+    }
+    Ok(user)
+}
+
+impl FromRequest for Admin {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(require_role(req, Role::Admin).map(|user| Admin { user }))
+    }
+}
+
+impl FromRequest for Moderator {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(require_role(req, Role::Moderator).map(|user| Moderator { user }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DbPool;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::middleware::auth::session_cookie;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    async fn admin_only(_: Admin) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    async fn moderators(_: Moderator) -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn session_for(pool: &DbPool, name: &str, role: Role) -> String {
+        setup_session(pool, name, role).1
+    }
+
+    #[actix_rt::test]
+    async fn it_guards_routes_by_role() {
+        let pool = setup_pool();
+        let sessions = vec![
+            (None, StatusCode::UNAUTHORIZED, StatusCode::UNAUTHORIZED),
+            (
+                Some(session_for(&pool, "viewer", Role::User)),
+                StatusCode::FORBIDDEN,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Some(session_for(&pool, "moderator", Role::Moderator)),
+                StatusCode::OK,
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Some(session_for(&pool, "admin", Role::Admin)),
+                StatusCode::OK,
+                StatusCode::OK,
+            ),
+        ];
+
+        let mut app = test::init_service(
+            App::new()
+                .data(pool)
+                .route("/moderators", web::get().to(moderators))
+                .route("/admin", web::get().to(admin_only)),
+        )
+        .await;
+
+        for (session, moderator_status, admin_status) in sessions {
+            for (uri, expected) in &[("/moderators", moderator_status), ("/admin", admin_status)] {
+                let mut req = test::TestRequest::get().uri(uri);
+                if let Some(session) = &session {
+                    req = req.cookie(session_cookie(session.clone()));
+                }
+                let resp = test::call_service(&mut app, req.to_request()).await;
+                assert_eq!(resp.status(), *expected, "{} {:?}", uri, session);
+            }
+        }
+    }
+}
@@ -1,54 +1,470 @@
-#![allow(dead_code)]
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Cookie};
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
 use chrono::{Duration, Utc};
+use futures::future::{ok, ready, LocalBoxFuture, Ready};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use uuid::Uuid;
+
+use std::task::{Context, Poll};
 
 use crate::config::CONFIG;
+use crate::database::DbPool;
 use crate::errors::ApiError;
+use crate::models::session::{self, Session};
+use crate::models::user::{self, User};
+
+/// Name of the cookie holding the session token
+pub const SESSION_COOKIE: &str = "session";
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PrivateClaim {
     id: String,
+    jti: String,
     exp: i64,
 }
 
 impl PrivateClaim {
-    pub fn new(id: &str) -> Self {
+    pub fn new(session: &Session) -> Self {
         Self {
-            id: String::from(id),
-            exp: (Utc::now() + Duration::hours(CONFIG.jwt_ttl)).timestamp(),
+            id: session.user_id.clone(),
+            jti: session.id.clone(),
+            exp: session.expires_at.timestamp(),
         }
     }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.jti
+    }
+}
+
+fn session_ttl() -> Duration {
+    Duration::hours(CONFIG.jwt_ttl)
+}
+
+/// Persist a new session for a user, returning its token
+pub fn start_session(pool: &DbPool, user_id: &str) -> Result<String, ApiError> {
+    // logins are rare enough to sweep out old sessions as they happen
+    let deleted = session::delete_inactive(pool)?;
+    if deleted > 0 {
+        log::info!("deleted {} expired or revoked sessions", deleted);
+    }
+
+    let s = session::create(pool, user_id, (Utc::now() + session_ttl()).naive_utc())?;
+    encode_session_cookie(PrivateClaim::new(&s))
 }
 
 pub fn encode_session_cookie(private_claim: PrivateClaim) -> Result<String, ApiError> {
-    let encoding_key = EncodingKey::from_secret(&CONFIG.jwt_key.as_ref());
+    let encoding_key = EncodingKey::from_secret(CONFIG.jwt_key.as_ref());
     encode(&Header::default(), &private_claim, &encoding_key)
         .map_err(|e| ApiError::CannotEncodeSessionToken(e.to_string()))
 }
 
 pub fn decode_session_cookie(token: &str) -> Result<PrivateClaim, ApiError> {
-    let decoding_key = DecodingKey::from_secret(&CONFIG.jwt_key.as_ref());
+    let decoding_key = DecodingKey::from_secret(CONFIG.jwt_key.as_ref());
     decode::<PrivateClaim>(token, &decoding_key, &Validation::default())
         .map(|data| data.claims)
         .map_err(|e| ApiError::CannotDecodeSessionToken(e.to_string()))
 }
 
+/// The cookie handed to a client after logging in, living as long as its token
+pub fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .max_age(session_ttl().num_seconds())
+        .finish()
+}
+
+/// The user a request's session token belongs to. Requests without a valid
+/// session are rejected with 401, banned users with 403.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub session_id: String,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+/// Session token from an `Authorization: Bearer` header or the session cookie
+fn session_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(String::from);
+
+    bearer.or_else(|| req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()))
+}
+
+pub(crate) fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, ApiError> {
+    let token =
+        session_token(req).ok_or_else(|| ApiError::Unauthorized(String::from("not logged in")))?;
+    let claim = decode_session_cookie(&token)?;
+    let id = Uuid::parse_str(claim.id())
+        .map_err(|_| ApiError::Unauthorized(String::from("invalid session")))?;
+
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .ok_or_else(|| ApiError::Internal(String::from("database unavailable")))?;
+    let session = session::get_by_id(pool, claim.session_id()).map_err(|e| match e {
+        ApiError::NotFound(_) => ApiError::Unauthorized(String::from("unknown session")),
+        e => e,
+    })?;
+    if !session.is_active() || session.user_id != claim.id() {
+        return Err(ApiError::Unauthorized(String::from(
+            "session expired or revoked",
+        )));
+    }
+
+    let user = user::get_by_id(pool, id).map_err(|e| match e {
+        ApiError::NotFound(_) => ApiError::Unauthorized(String::from("unknown user")),
+        e => e,
+    })?;
+
+    if user.is_banned {
+        let reason = user.ban_reason.as_deref().unwrap_or("no reason given");
+        return Err(ApiError::Forbidden(format!("banned: {}", reason)));
+    }
+
+    refresh_session(req, pool, &session)?;
+    Ok(AuthenticatedUser {
+        user,
+        session_id: session.id,
+    })
+}
+
+/// A token issued for a session extended while handling the request,
+/// sent back as a cookie by `SessionRefresh`
+#[derive(Debug, Clone)]
+struct RefreshedSession(String);
+
+/// Extend sessions with less than half of their lifetime left
+fn refresh_session(req: &HttpRequest, pool: &DbPool, s: &Session) -> Result<(), ApiError> {
+    let now = Utc::now();
+    if s.expires_at - now.naive_utc() >= session_ttl() / 2 {
+        return Ok(());
+    }
+
+    let mut refreshed = s.clone();
+    refreshed.expires_at = (now + session_ttl()).naive_utc();
+    session::extend(pool, &s.id, refreshed.expires_at)?;
+    let token = encode_session_cookie(PrivateClaim::new(&refreshed))?;
+
+    req.extensions_mut().insert(RefreshedSession(token));
+    Ok(())
+}
+
+/// Middleware handing out a new session cookie when `AuthenticatedUser`
+/// extended the session of a request
+pub struct SessionRefresh;
+
+impl<S, B> Transform<S> for SessionRefresh
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionRefreshMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionRefreshMiddleware { service })
+    }
+}
+
+pub struct SessionRefreshMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for SessionRefreshMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let refreshed = res
+                .request()
+                .extensions()
+                .get::<RefreshedSession>()
+                .cloned();
+            // leave responses that set the session themselves alone, e.g. logout
+            let sets_session = res.response().cookies().any(|c| c.name() == SESSION_COOKIE);
+
+            if let (Some(RefreshedSession(token)), false) = (refreshed, sets_session) {
+                res.response_mut()
+                    .add_cookie(&session_cookie(token))
+                    .map_err(|e| ApiError::Internal(e.to_string()))?;
+            }
+            Ok(res)
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::channel::Channel;
+    use crate::errors::ErrorResponse;
+    use crate::helpers::setup_pool;
+    use actix_web::{http::StatusCode, test, App, HttpResponse, ResponseError};
+
+    fn create_user(pool: &DbPool) -> User {
+        let chn = Channel::new(
+            String::from("jbpratt"),
+            String::from("twitch"),
+            String::new(),
+        );
+        user::create(pool, 8, chn.unwrap(), "jbpratt", "0.0.0.0").unwrap()
+    }
+
+    fn session_for(user_id: &str, ttl: Duration) -> Session {
+        let now = Utc::now().naive_utc();
+        Session {
+            id: Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            expires_at: now + ttl,
+            revoked_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    async fn extract(req: test::TestRequest) -> Result<AuthenticatedUser, ApiError> {
+        let req = req.to_http_request();
+        AuthenticatedUser::from_request(&req, &mut Payload::None).await
+    }
+
+    fn status(res: Result<AuthenticatedUser, ApiError>) -> StatusCode {
+        res.unwrap_err().status_code()
+    }
 
     #[test]
     fn it_encodes_a_session_cookie() {
-        let private_claim = PrivateClaim::new("134109801571028");
+        let private_claim = PrivateClaim::new(&session_for("134109801571028", session_ttl()));
         let jwt = encode_session_cookie(private_claim);
         assert!(jwt.is_ok());
     }
 
     #[test]
     fn it_decodes_a_session_cookie() {
-        let private_claim = PrivateClaim::new("134109801571028");
+        let private_claim = PrivateClaim::new(&session_for("134109801571028", session_ttl()));
         let jwt = encode_session_cookie(private_claim.clone()).unwrap();
         let decoded = decode_session_cookie(&jwt).unwrap();
         assert_eq!(private_claim, decoded);
     }
+
+    #[actix_rt::test]
+    async fn it_authenticates_a_session_cookie_or_bearer_token() {
+        let pool = setup_pool();
+        let user = create_user(&pool);
+        let jwt = start_session(&pool, &user.id).unwrap();
+        let jti = decode_session_cookie(&jwt).unwrap().jti;
+
+        let req = test::TestRequest::default()
+            .data(pool.clone())
+            .cookie(session_cookie(jwt.clone()));
+        let auth = extract(req).await.unwrap();
+        assert_eq!(auth.user.id, user.id);
+        assert_eq!(auth.session_id, jti);
+
+        let req = test::TestRequest::default()
+            .data(pool)
+            .header(header::AUTHORIZATION, format!("Bearer {}", jwt));
+        assert_eq!(extract(req).await.unwrap().user.id, user.id);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_missing_or_invalid_sessions() {
+        let pool = setup_pool();
+        let user = create_user(&pool);
+        // a valid signature for a session that was never stored
+        let unknown =
+            encode_session_cookie(PrivateClaim::new(&session_for(&user.id, session_ttl())));
+        // a stored session presented with someone else's user id
+        let mut forged = PrivateClaim::new(
+            &session::create(
+                &pool,
+                &user.id,
+                session_for(&user.id, session_ttl()).expires_at,
+            )
+            .unwrap(),
+        );
+        forged.id = Uuid::new_v4().to_string();
+        let forged = encode_session_cookie(forged);
+
+        for token in &[
+            None,
+            Some(String::from("garbage")),
+            Some(unknown.unwrap()),
+            Some(forged.unwrap()),
+        ] {
+            let mut req = test::TestRequest::default().data(pool.clone());
+            if let Some(token) = token {
+                req = req.cookie(session_cookie(token.clone()));
+            }
+            assert_eq!(status(extract(req).await), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_auth_failures_in_the_error_envelope() {
+        async fn whoami(auth: AuthenticatedUser) -> HttpResponse {
+            HttpResponse::Ok().body(auth.user.id)
+        }
+
+        let pool = setup_pool();
+        let mut banned = create_user(&pool);
+        banned.is_banned = true;
+        user::update(&pool, &banned).unwrap();
+        let jwt = start_session(&pool, &banned.id).unwrap();
+        let mut app =
+            test::init_service(App::new().data(pool).route("/", web::get().to(whoami))).await;
+
+        let cases = [
+            (
+                None,
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "not logged in",
+            ),
+            (
+                Some(jwt),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "banned: no reason given",
+            ),
+        ];
+        for (token, status, code, message) in cases.iter() {
+            let mut req = test::TestRequest::get().uri("/");
+            if let Some(token) = token {
+                req = req.cookie(session_cookie(token.clone()));
+            }
+            let resp = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(resp.status(), *status);
+            let body: ErrorResponse = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+            assert_eq!(body.error.code, *code);
+            assert_eq!(body.error.message, *message);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_revoked_sessions() {
+        let pool = setup_pool();
+        let user = create_user(&pool);
+        let jwt = start_session(&pool, &user.id).unwrap();
+
+        let session_id = decode_session_cookie(&jwt)
+            .unwrap()
+            .session_id()
+            .to_string();
+        session::revoke(&pool, &session_id).unwrap();
+        let req = test::TestRequest::default()
+            .data(pool.clone())
+            .cookie(session_cookie(jwt.clone()));
+        assert_eq!(status(extract(req).await), StatusCode::UNAUTHORIZED);
+
+        // the next login deletes the revoked session, its token stays refused
+        start_session(&pool, &user.id).unwrap();
+        assert!(session::get_by_id(&pool, &session_id).is_err());
+        let req = test::TestRequest::default()
+            .data(pool)
+            .cookie(session_cookie(jwt));
+        assert_eq!(status(extract(req).await), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_banned_users() {
+        let pool = setup_pool();
+        let mut banned = create_user(&pool);
+        banned.is_banned = true;
+        user::update(&pool, &banned).unwrap();
+
+        let jwt = start_session(&pool, &banned.id).unwrap();
+        let req = test::TestRequest::default()
+            .data(pool)
+            .cookie(session_cookie(jwt));
+        assert_eq!(status(extract(req).await), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn it_refreshes_sessions_close_to_expiry() {
+        async fn whoami(auth: AuthenticatedUser) -> HttpResponse {
+            HttpResponse::Ok().body(auth.user.id)
+        }
+
+        let pool = setup_pool();
+        let user = create_user(&pool);
+        let fresh = start_session(&pool, &user.id).unwrap();
+        let expiring = session::create(
+            &pool,
+            &user.id,
+            (Utc::now() + Duration::minutes(5)).naive_utc(),
+        )
+        .unwrap();
+        let old = encode_session_cookie(PrivateClaim::new(&expiring)).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .data(pool.clone())
+                .wrap(SessionRefresh)
+                .route("/", web::get().to(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .cookie(session_cookie(fresh))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.response().cookies().next().is_none());
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .cookie(session_cookie(old))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == SESSION_COOKIE)
+            .unwrap();
+        let claim = decode_session_cookie(cookie.value()).unwrap();
+        assert_eq!(claim.session_id(), expiring.id);
+
+        let extended = session::get_by_id(&pool, &expiring.id).unwrap();
+        assert!(extended.expires_at > expiring.expires_at);
+        assert_eq!(claim.exp, extended.expires_at.timestamp());
+    }
 }
@@ -0,0 +1,212 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use futures::future::{ok, Either, Ready};
+
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+use crate::errors::ApiError;
+use crate::models::banned_ip_range::IpBanList;
+
+/// The client address of a request, after looking through trusted proxies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+/// The client address resolved by `IpBan`, if the request passed through it
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    req.extensions().get::<ClientIp>().map(|ip| ip.0)
+}
+
+/// Resolve the client address from the peer address, trusting
+/// `X-Forwarded-For` only when the peer is one of our proxies
+fn resolve_client_ip(req: &ServiceRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded: Vec<IpAddr> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|addr| addr.trim().parse().ok())
+        .collect();
+
+    // walk back from the closest hop, every proxy we trust may have appended
+    // to the header but anything before the first untrusted hop is the client's
+    Some(
+        forwarded
+            .iter()
+            .rev()
+            .find(|addr| !trusted_proxies.contains(addr))
+            .or_else(|| forwarded.first())
+            .copied()
+            .unwrap_or(peer),
+    )
+}
+
+/// Middleware rejecting requests from banned ip ranges with 403
+pub struct IpBan {
+    bans: IpBanList,
+    trusted_proxies: Rc<Vec<IpAddr>>,
+}
+
+impl IpBan {
+    pub fn new(bans: IpBanList, trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            bans,
+            trusted_proxies: Rc::new(trusted_proxies),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for IpBan
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IpBanMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IpBanMiddleware {
+            service,
+            bans: self.bans.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        })
+    }
+}
+
+pub struct IpBanMiddleware<S> {
+    service: S,
+    bans: IpBanList,
+    trusted_proxies: Rc<Vec<IpAddr>>,
+}
+
+impl<S, B> Service for IpBanMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let ip = match resolve_client_ip(&req, &self.trusted_proxies) {
+            Some(ip) => ip,
+            None => return Either::Left(self.service.call(req)),
+        };
+        req.extensions_mut().insert(ClientIp(ip));
+
+        if let Some(range) = self.bans.check(ip) {
+            log::info!(
+                "rejected {} {} from {}, banned by {} - {}: {}",
+                req.method(),
+                req.path(),
+                ip,
+                range.start,
+                range.end,
+                range.note.as_deref().unwrap_or("")
+            );
+            return Either::Right(ok(
+                req.error_response(ApiError::Forbidden(String::from("banned")))
+            ));
+        }
+
+        Either::Left(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::setup_pool;
+    use crate::models::banned_ip_range::{BannedIpRange, IpBanList};
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    use std::net::SocketAddr;
+
+    async fn echo_ip(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(client_ip(&req).map(|ip| ip.to_string()).unwrap_or_default())
+    }
+
+    fn request(peer: &str, forwarded: Option<&str>) -> test::TestRequest {
+        let peer: SocketAddr = format!("{}:4000", peer).parse().unwrap();
+        let req = test::TestRequest::get().uri("/").peer_addr(peer);
+        match forwarded {
+            Some(forwarded) => req.header(X_FORWARDED_FOR, forwarded),
+            None => req,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_banned_addresses() {
+        let pool = setup_pool();
+        let range = BannedIpRange {
+            start: String::from("10.0.0.0"),
+            end: String::from("10.255.255.255"),
+            note: Some(String::from("spam")),
+            ..Default::default()
+        };
+        let bans = IpBanList::load(pool).unwrap();
+        bans.insert(&range).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(IpBan::new(bans, Vec::new()))
+                .route("/", web::get().to(echo_ip)),
+        )
+        .await;
+
+        let resp = test::call_service(&mut app, request("10.1.2.3", None).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = test::call_service(&mut app, request("192.168.0.1", None).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn it_only_trusts_forwarded_headers_from_proxies() {
+        let proxy: IpAddr = "172.16.0.1".parse().unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(IpBan::new(
+                    IpBanList::load(setup_pool()).unwrap(),
+                    vec![proxy],
+                ))
+                .route("/", web::get().to(echo_ip)),
+        )
+        .await;
+
+        let cases = [
+            ("192.168.0.1", Some("10.0.0.1"), "192.168.0.1"),
+            ("172.16.0.1", None, "172.16.0.1"),
+            ("172.16.0.1", Some("10.0.0.1"), "10.0.0.1"),
+            (
+                "172.16.0.1",
+                Some("1.1.1.1, 10.0.0.1, 172.16.0.1"),
+                "10.0.0.1",
+            ),
+            ("172.16.0.1", Some("172.16.0.1"), "172.16.0.1"),
+        ];
+        for (peer, forwarded, expected) in cases.iter() {
+            let req = request(peer, *forwarded).to_request();
+            let body = test::read_response(&mut app, req).await;
+            assert_eq!(body, expected.as_bytes(), "{} {:?}", peer, forwarded);
+        }
+    }
+}
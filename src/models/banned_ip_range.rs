@@ -1,7 +1,8 @@
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
 
 use crate::database::DbPool;
 use crate::errors::ApiError;
@@ -47,43 +48,200 @@ pub fn remove(pool: &DbPool, range: &BannedIpRange) -> anyhow::Result<(), ApiErr
     use crate::schema::banned_ip_ranges::dsl::{end, start};
 
     let conn = pool.get()?;
-    diesel::delete(
+    let removed = diesel::delete(
         banned_ip_ranges::table
             .filter(start.eq(range.start.clone()))
             .filter(end.eq(range.end.clone())),
     )
     .execute(&conn)?;
 
+    if removed == 0 {
+        return Err(ApiError::NotFound(format!(
+            "range {} - {} is not banned",
+            range.start, range.end
+        )));
+    }
     Ok(())
 }
 
-pub fn check_if_banned(
-    pool: &DbPool,
-    addr: &str,
-) -> anyhow::Result<Option<BannedIpRange>, ApiError> {
-    let ranges = get_all(&pool)?;
-    let result = parse_ip(addr)?;
-
-    for range in ranges {
-        let start = parse_ip(range.start.as_str())?;
-        let end = parse_ip(range.end.as_str())?;
-        if start < result && result < end {
-            return Ok(Some(range));
+/// Validate a start/end pair, returning both addresses in canonical form
+pub fn range_bounds(start: &str, end: &str) -> Result<(IpAddr, IpAddr), ApiError> {
+    let parse = |addr: &str| {
+        addr.trim()
+            .parse::<IpAddr>()
+            .map_err(|_| ApiError::CannotParseIPAddr(addr.to_string()))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+
+    if start.is_ipv4() != end.is_ipv4() {
+        return Err(ApiError::InvalidIpRange(format!(
+            "{} and {} are different address families",
+            start, end
+        )));
+    }
+    if start > end {
+        return Err(ApiError::InvalidIpRange(format!(
+            "{} is after {}",
+            start, end
+        )));
+    }
+    Ok((start, end))
+}
+
+/// The first and last address of a CIDR block such as `10.0.0.0/8`
+pub fn cidr_bounds(cidr: &str) -> Result<(IpAddr, IpAddr), ApiError> {
+    let invalid = || ApiError::InvalidIpRange(format!("invalid cidr: {}", cidr));
+
+    let mut parts = cidr.trim().splitn(2, '/');
+    let addr = parts
+        .next()
+        .unwrap_or_default()
+        .parse::<IpAddr>()
+        .map_err(|_| ApiError::CannotParseIPAddr(cidr.to_string()))?;
+    let prefix = parts
+        .next()
+        .ok_or_else(invalid)?
+        .parse::<u32>()
+        .map_err(|_| invalid())?;
+
+    match addr {
+        IpAddr::V4(addr) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let start = u32::from(addr) & mask;
+            Ok((
+                Ipv4Addr::from(start).into(),
+                Ipv4Addr::from(start | !mask).into(),
+            ))
+        }
+        IpAddr::V6(addr) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            let start = u128::from(addr) & mask;
+            Ok((
+                Ipv6Addr::from(start).into(),
+                Ipv6Addr::from(start | !mask).into(),
+            ))
         }
+        _ => Err(invalid()),
+    }
+}
+
+/// Map an address onto one 128-bit space, with IPv4 addresses IPv4-mapped
+/// so they compare equal to the same client connecting over IPv6
+pub fn ip_to_u128(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u128::from(addr.to_ipv6_mapped()),
+        IpAddr::V6(addr) => u128::from(addr),
     }
+}
+
+#[derive(Debug)]
+struct IndexEntry {
+    start: u128,
+    end: u128,
+    // the largest end of this and every earlier entry
+    max_end: u128,
+    range: BannedIpRange,
+}
 
-    Ok(None)
+/// Banned ranges sorted by their start, for lookups in logarithmic time
+/// in the common case of few overlapping ranges. Both bounds are inclusive.
+#[derive(Debug, Default)]
+pub struct IpRangeIndex {
+    entries: Vec<IndexEntry>,
 }
 
-// Parse an IP address and return a Vec<u8> of octets
-fn parse_ip(ip_addr: &str) -> anyhow::Result<Vec<u8>, ApiError> {
-    let check_ip: IpAddr = ip_addr
-        .parse()
-        .map_err(|_| ApiError::CannotParseIPAddr(ip_addr.to_string()))?;
+impl IpRangeIndex {
+    pub fn new(ranges: Vec<BannedIpRange>) -> Self {
+        let mut entries: Vec<IndexEntry> = ranges
+            .into_iter()
+            .filter_map(|range| match range_bounds(&range.start, &range.end) {
+                Ok((start, end)) => Some(IndexEntry {
+                    start: ip_to_u128(start),
+                    end: ip_to_u128(end),
+                    max_end: 0,
+                    range,
+                }),
+                Err(e) => {
+                    log::error!("skipping invalid banned ip range: {}", e);
+                    None
+                }
+            })
+            .collect();
 
-    match check_ip {
-        IpAddr::V4(ip) => Ok(ip.octets().to_vec()),
-        IpAddr::V6(ip) => Ok(ip.octets().to_vec()),
+        entries.sort_by_key(|e| e.start);
+        let mut max_end = 0;
+        for e in entries.iter_mut() {
+            max_end = max_end.max(e.end);
+            e.max_end = max_end;
+        }
+        Self { entries }
+    }
+
+    /// The first range containing the address, if any
+    pub fn find(&self, addr: IpAddr) -> Option<&BannedIpRange> {
+        let ip = ip_to_u128(addr);
+        let candidates = self.entries.partition_point(|e| e.start <= ip);
+
+        // walking back, once no earlier range reaches the address none will
+        self.entries[..candidates]
+            .iter()
+            .rev()
+            .take_while(|e| e.max_end >= ip)
+            .find(|e| e.end >= ip)
+            .map(|e| &e.range)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// The banned ranges, kept in an in-memory index that is rebuilt from the
+/// database whenever a range is added or removed
+#[derive(Clone)]
+pub struct IpBanList {
+    pool: DbPool,
+    index: Arc<RwLock<IpRangeIndex>>,
+}
+
+impl IpBanList {
+    pub fn load(pool: DbPool) -> Result<Self, ApiError> {
+        let list = Self {
+            pool,
+            index: Arc::new(RwLock::new(IpRangeIndex::new(Vec::new()))),
+        };
+        list.reload()?;
+        Ok(list)
+    }
+
+    /// Rebuild the whole index from the database. Every insert and remove
+    /// goes through here, so a write costs a full reload of the table and
+    /// only lookups are logarithmic.
+    pub fn reload(&self) -> Result<(), ApiError> {
+        let index = IpRangeIndex::new(get_all(&self.pool)?);
+        log::info!("loaded {} banned ip ranges", index.len());
+        *self.index.write().expect("ip ban index poisoned") = index;
+        Ok(())
+    }
+
+    pub fn check(&self, addr: IpAddr) -> Option<BannedIpRange> {
+        let index = self.index.read().expect("ip ban index poisoned");
+        index.find(addr).cloned()
+    }
+
+    pub fn get_all(&self) -> Result<Vec<BannedIpRange>, ApiError> {
+        get_all(&self.pool)
+    }
+
+    pub fn insert(&self, range: &BannedIpRange) -> Result<BannedIpRange, ApiError> {
+        let range = insert(&self.pool, range)?;
+        self.reload()?;
+        Ok(range)
+    }
+
+    pub fn remove(&self, range: &BannedIpRange) -> Result<(), ApiError> {
+        remove(&self.pool, range)?;
+        self.reload()
     }
 }
 
@@ -91,6 +249,19 @@ fn parse_ip(ip_addr: &str) -> anyhow::Result<Vec<u8>, ApiError> {
 mod tests {
     use super::*;
     use crate::helpers::setup_pool;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn range(start: &str, end: &str) -> BannedIpRange {
+        BannedIpRange {
+            start: start.to_string(),
+            end: end.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
 
     #[test]
     fn it_inserts_a_banned_ip_range() {
@@ -140,10 +311,8 @@ mod tests {
     #[test]
     fn it_checks_and_doesnt_find_a_banned_ip_range() {
         let pool = setup_pool();
-        let results = check_if_banned(&pool, "10.0.0.16");
-        assert!(results.is_ok());
-        let results = results.unwrap();
-        assert!(results.is_none());
+        let bans = IpBanList::load(pool).unwrap();
+        assert!(bans.check("10.0.0.16".parse().unwrap()).is_none());
     }
 
     #[test]
@@ -159,11 +328,56 @@ mod tests {
         let created = insert(&pool, &banned_ip_range.clone());
         assert!(created.is_ok());
 
-        let check = check_if_banned(&pool, "127.0.0.30");
-        assert!(check.is_ok());
+        let bans = IpBanList::load(pool).unwrap();
+        assert!(bans.check("127.0.0.30".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn it_validates_range_bounds() {
+        let (start, end) = range_bounds("2001:0db8:0000::", "2001:db8::ffff").unwrap();
+        assert_eq!(start.to_string(), "2001:db8::");
+        assert_eq!(end.to_string(), "2001:db8::ffff");
+        assert!(range_bounds("10.0.0.1", "10.0.0.1").is_ok());
 
-        let check = check.unwrap();
-        assert!(check.is_some());
+        assert_eq!(
+            range_bounds("10.0.0.2", "10.0.0.1").unwrap_err(),
+            ApiError::InvalidIpRange(String::from("10.0.0.2 is after 10.0.0.1"))
+        );
+        assert!(matches!(
+            range_bounds("10.0.0.1", "::1"),
+            Err(ApiError::InvalidIpRange(_))
+        ));
+        assert!(matches!(
+            range_bounds("10.0.0", "10.0.0.1"),
+            Err(ApiError::CannotParseIPAddr(_))
+        ));
+    }
+
+    #[test]
+    fn it_parses_cidr_bounds() {
+        let cases = [
+            ("10.0.0.0/8", "10.0.0.0", "10.255.255.255"),
+            ("192.168.1.77/24", "192.168.1.0", "192.168.1.255"),
+            ("1.2.3.4/32", "1.2.3.4", "1.2.3.4"),
+            ("0.0.0.0/0", "0.0.0.0", "255.255.255.255"),
+            (
+                "2001:db8::/32",
+                "2001:db8::",
+                "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff",
+            ),
+            ("::/0", "::", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+        ];
+        for (cidr, start, end) in cases.iter() {
+            let (s, e) = cidr_bounds(cidr).unwrap();
+            assert_eq!(
+                (s.to_string(), e.to_string()),
+                (start.to_string(), end.to_string())
+            );
+        }
+
+        for cidr in &["10.0.0.0/33", "10.0.0.0", "10.0.0.0/x", "2001:db8::/129"] {
+            assert!(cidr_bounds(cidr).is_err(), "{}", cidr);
+        }
     }
 
     #[test]
@@ -181,5 +395,143 @@ mod tests {
 
         let removed = remove(&pool, &banned_ip_range.clone());
         assert!(removed.is_ok());
+
+        let removed = remove(&pool, &banned_ip_range);
+        assert!(matches!(removed, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn it_bans_range_endpoints_inclusively() {
+        let index = IpRangeIndex::new(vec![
+            range("10.0.0.10", "10.0.0.20"),
+            range("2001:db8::", "2001:db8::ff"),
+            range("192.168.1.1", "192.168.1.1"),
+        ]);
+
+        for addr in &[
+            "10.0.0.10",
+            "10.0.0.20",
+            "2001:db8::",
+            "2001:db8::ff",
+            "192.168.1.1",
+            "::ffff:10.0.0.15",
+        ] {
+            assert!(index.find(ip(addr)).is_some(), "{}", addr);
+        }
+        for addr in &[
+            "10.0.0.9",
+            "10.0.0.21",
+            "2001:db7:ffff:ffff:ffff:ffff:ffff:ffff",
+            "2001:db8::100",
+            "192.168.1.0",
+            "192.168.1.2",
+        ] {
+            assert!(index.find(ip(addr)).is_none(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn it_matches_the_edges_of_the_address_space() {
+        let index = IpRangeIndex::new(vec![
+            range("0.0.0.0", "0.0.0.0"),
+            range("255.255.255.255", "255.255.255.255"),
+            range("::", "::"),
+            range(
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+            ),
+        ]);
+
+        for addr in &[
+            "0.0.0.0",
+            "255.255.255.255",
+            "::",
+            "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+        ] {
+            assert!(index.find(ip(addr)).is_some(), "{}", addr);
+        }
+        // neighbours of the IPv4-mapped block must not leak into it
+        for addr in &[
+            "0.0.0.1",
+            "255.255.255.254",
+            "::1",
+            "::fffe:ffff:ffff",
+            "::1:0:0:0",
+        ] {
+            assert!(index.find(ip(addr)).is_none(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn it_finds_addresses_inside_nested_ranges() {
+        let index = IpRangeIndex::new(vec![
+            range("10.0.0.0", "10.255.255.255"),
+            range("10.0.0.5", "10.0.0.6"),
+            range("10.1.0.0", "10.1.0.1"),
+        ]);
+        assert_eq!(index.len(), 3);
+        // covered only by the wide range that starts before the nested ones
+        assert_eq!(index.find(ip("10.2.0.0")).unwrap().end, "10.255.255.255");
+        assert!(index.find(ip("11.0.0.0")).is_none());
+    }
+
+    /// compare the index against a linear scan over random ranges and probes
+    fn check_against_scan(rng: &mut StdRng, v4: bool) {
+        let random_ip = |rng: &mut StdRng| -> IpAddr {
+            if v4 {
+                Ipv4Addr::from(rng.gen::<u32>()).into()
+            } else {
+                Ipv6Addr::from(rng.gen::<u128>()).into()
+            }
+        };
+
+        let mut ranges = Vec::new();
+        for _ in 0..rng.gen_range(1, 20) {
+            let (a, b) = (random_ip(rng), random_ip(rng));
+            let (start, end) = if a <= b { (a, b) } else { (b, a) };
+            ranges.push(range(&start.to_string(), &end.to_string()));
+        }
+        let index = IpRangeIndex::new(ranges.clone());
+
+        let mut probes: Vec<IpAddr> = (0..50).map(|_| random_ip(rng)).collect();
+        for r in &ranges {
+            let (start, end) = (ip_to_u128(ip(&r.start)), ip_to_u128(ip(&r.end)));
+            for edge in &[start.wrapping_sub(1), start, end, end.wrapping_add(1)] {
+                let addr = Ipv6Addr::from(*edge);
+                probes.push(match addr.to_ipv4_mapped() {
+                    Some(v4) => v4.into(),
+                    None => addr.into(),
+                });
+            }
+        }
+
+        for probe in probes {
+            let ip_value = ip_to_u128(probe);
+            let expected = ranges.iter().any(|r| {
+                ip_to_u128(ip(&r.start)) <= ip_value && ip_value <= ip_to_u128(ip(&r.end))
+            });
+            assert_eq!(index.find(probe).is_some(), expected, "{}", probe);
+        }
+    }
+
+    #[test]
+    fn it_agrees_with_a_linear_scan_for_random_ranges() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..200 {
+            check_against_scan(&mut rng, true);
+            check_against_scan(&mut rng, false);
+        }
+    }
+
+    #[test]
+    fn it_rebuilds_the_index_on_insert_and_remove() {
+        let bans = IpBanList::load(setup_pool()).unwrap();
+        let banned = range("10.0.0.1", "10.0.0.1");
+
+        assert!(bans.check(ip("10.0.0.1")).is_none());
+        bans.insert(&banned).unwrap();
+        assert!(bans.check(ip("10.0.0.1")).is_some());
+        bans.remove(&banned).unwrap();
+        assert!(bans.check(ip("10.0.0.1")).is_none());
     }
 }
@@ -1,7 +1,7 @@
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 
-use crate::channel::valid_service;
+use crate::channel::{normalize_channel, valid_service};
 use crate::database::DbPool;
 use crate::errors::ApiError;
 use crate::schema::banned_streams;
@@ -14,6 +14,7 @@ pub struct BannedStream {
     pub reason: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub banned_by: Option<String>,
 }
 
 impl Default for BannedStream {
@@ -24,17 +25,46 @@ impl Default for BannedStream {
             reason: None,
             created_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc(),
+            banned_by: None,
         }
     }
 }
 
-fn get_all(pool: &DbPool) -> anyhow::Result<Vec<BannedStream>, ApiError> {
+impl BannedStream {
+    /// The reason shown to viewers of the banned stream
+    pub fn reason_or_default(&self) -> String {
+        self.reason
+            .clone()
+            .unwrap_or_else(|| String::from("stream banned"))
+    }
+}
+
+pub fn get_all(pool: &DbPool) -> Result<Vec<BannedStream>, ApiError> {
     let conn = pool.get()?;
     let all = banned_streams::table.load(&conn)?;
     Ok(all)
 }
 
-fn insert(pool: &DbPool, stream: &BannedStream) -> anyhow::Result<BannedStream, ApiError> {
+/// The ban on a channel, if any, normalizing the channel the way streams store it
+pub fn find(
+    pool: &DbPool,
+    stream_service: &str,
+    stream_channel: &str,
+) -> Result<Option<BannedStream>, ApiError> {
+    use crate::schema::banned_streams::dsl::{channel, service};
+
+    let normalized = normalize_channel(stream_service, stream_channel)?;
+    let conn = pool.get()?;
+
+    let found = banned_streams::table
+        .filter(service.eq(stream_service))
+        .filter(channel.eq(normalized))
+        .first(&conn)
+        .optional()?;
+    Ok(found)
+}
+
+pub fn insert(pool: &DbPool, stream: &BannedStream) -> Result<BannedStream, ApiError> {
     let conn = pool.get()?;
 
     if !valid_service(stream.service.as_str()) {
@@ -47,17 +77,23 @@ fn insert(pool: &DbPool, stream: &BannedStream) -> anyhow::Result<BannedStream,
     Ok(stream.clone())
 }
 
-fn remove(pool: &DbPool, stream: &BannedStream) -> anyhow::Result<(), ApiError> {
+pub fn remove(pool: &DbPool, stream: &BannedStream) -> Result<(), ApiError> {
     use crate::schema::banned_streams::dsl::{channel, service};
 
     let conn = pool.get()?;
-    diesel::delete(
+    let removed = diesel::delete(
         banned_streams::table
             .filter(channel.eq(stream.channel.clone()))
             .filter(service.eq(stream.service.clone())),
     )
     .execute(&conn)?;
 
+    if removed == 0 {
+        return Err(ApiError::NotFound(format!(
+            "stream {}/{} is not banned",
+            stream.service, stream.channel
+        )));
+    }
     Ok(())
 }
 
@@ -126,6 +162,30 @@ mod tests {
 
         let removed = remove(&pool, &banned_stream.clone());
         assert!(removed.is_ok());
+
+        let removed = remove(&pool, &banned_stream);
+        assert!(matches!(removed, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn it_finds_a_ban_by_normalized_channel() {
+        let pool = setup_pool();
+        let banned_stream = BannedStream {
+            channel: String::from("https://example.com/live.m3u8"),
+            service: String::from("m3u8"),
+            ..Default::default()
+        };
+        insert(&pool, &banned_stream).unwrap();
+
+        let found = find(&pool, "m3u8", "HTTPS://EXAMPLE.COM/live.m3u8").unwrap();
+        assert_eq!(found.unwrap().reason_or_default(), "stream banned");
+
+        assert!(find(&pool, "advanced", "https://example.com/live.m3u8")
+            .unwrap()
+            .is_none());
+        assert!(find(&pool, "m3u8", "https://example.com/other.m3u8")
+            .unwrap()
+            .is_none());
     }
 
     #[test]
@@ -1,4 +1,5 @@
 pub mod banned_ip_range;
 pub mod banned_streams;
+pub mod session;
 pub mod stream;
 pub mod user;
@@ -0,0 +1,210 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::schema::sessions;
+
+/// A login, identified in session tokens by their `jti` claim
+#[derive(Queryable, Debug, Clone, Insertable, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Session {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now().naive_utc()
+    }
+}
+
+pub fn create(
+    pool: &DbPool,
+    user_id: &str,
+    expires_at: NaiveDateTime,
+) -> Result<Session, ApiError> {
+    let conn = pool.get()?;
+
+    let now = Utc::now().naive_utc();
+    let session = Session {
+        id: Uuid::new_v4().to_hyphenated().to_string(),
+        user_id: user_id.to_string(),
+        expires_at,
+        revoked_at: None,
+        created_at: now,
+        updated_at: now,
+    };
+
+    diesel::insert_into(sessions::table)
+        .values(&session)
+        .execute(&conn)?;
+    Ok(session)
+}
+
+pub fn get_by_id(pool: &DbPool, session_id: &str) -> Result<Session, ApiError> {
+    use crate::schema::sessions::dsl::id;
+
+    let conn = pool.get()?;
+    sessions::table
+        .filter(id.eq(session_id))
+        .first::<Session>(&conn)
+        .map_err(|_| ApiError::NotFound(format!("failed to find session with id: {}", session_id)))
+}
+
+/// Push back the expiry of a session that is still active
+pub fn extend(
+    pool: &DbPool,
+    session_id: &str,
+    new_expires_at: NaiveDateTime,
+) -> Result<(), ApiError> {
+    use crate::schema::sessions::dsl::{expires_at, id, revoked_at, updated_at};
+
+    let conn = pool.get()?;
+    let updated = diesel::update(
+        sessions::table
+            .filter(id.eq(session_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set((
+        expires_at.eq(new_expires_at),
+        updated_at.eq(Utc::now().naive_utc()),
+    ))
+    .execute(&conn)?;
+
+    if updated == 0 {
+        return Err(ApiError::NotFound(format!(
+            "failed to find active session with id: {}",
+            session_id
+        )));
+    }
+    Ok(())
+}
+
+pub fn revoke(pool: &DbPool, session_id: &str) -> Result<(), ApiError> {
+    use crate::schema::sessions::dsl::{id, revoked_at, updated_at};
+
+    let conn = pool.get()?;
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        sessions::table
+            .filter(id.eq(session_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set((revoked_at.eq(now), updated_at.eq(now)))
+    .execute(&conn)?;
+    Ok(())
+}
+
+/// Revoke every active session of a user, returning how many were revoked
+pub fn revoke_all_for_user(pool: &DbPool, uid: &str) -> Result<usize, ApiError> {
+    use crate::schema::sessions::dsl::{expires_at, revoked_at, updated_at, user_id};
+
+    let conn = pool.get()?;
+    let now = Utc::now().naive_utc();
+    let revoked = diesel::update(
+        sessions::table
+            .filter(user_id.eq(uid))
+            .filter(revoked_at.is_null())
+            .filter(expires_at.gt(now)),
+    )
+    .set((revoked_at.eq(now), updated_at.eq(now)))
+    .execute(&conn)?;
+    Ok(revoked)
+}
+
+/// Delete every expired or revoked session, returning how many were deleted.
+/// Tokens of deleted sessions are refused as unknown.
+pub fn delete_inactive(pool: &DbPool) -> Result<usize, ApiError> {
+    use crate::schema::sessions::dsl::{expires_at, revoked_at};
+
+    let conn = pool.get()?;
+    let deleted = diesel::delete(
+        sessions::table.filter(
+            revoked_at
+                .is_not_null()
+                .or(expires_at.le(Utc::now().naive_utc())),
+        ),
+    )
+    .execute(&conn)?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::models::user::Role;
+    use chrono::Duration;
+
+    fn in_hours(hours: i64) -> NaiveDateTime {
+        (Utc::now() + Duration::hours(hours)).naive_utc()
+    }
+
+    #[test]
+    fn it_creates_extends_and_revokes_a_session() {
+        let pool = setup_pool();
+        let (u, _) = setup_session(&pool, "rustler", Role::User);
+
+        let session = create(&pool, &u.id, in_hours(1)).unwrap();
+        assert!(get_by_id(&pool, &session.id).unwrap().is_active());
+
+        extend(&pool, &session.id, in_hours(2)).unwrap();
+        let extended = get_by_id(&pool, &session.id).unwrap();
+        assert!(extended.expires_at > session.expires_at);
+
+        revoke(&pool, &session.id).unwrap();
+        assert!(!get_by_id(&pool, &session.id).unwrap().is_active());
+        assert!(matches!(
+            extend(&pool, &session.id, in_hours(3)),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn it_treats_an_expired_session_as_inactive() {
+        let pool = setup_pool();
+        let (u, _) = setup_session(&pool, "rustler", Role::User);
+
+        let expired = create(&pool, &u.id, in_hours(-1)).unwrap();
+        assert!(!get_by_id(&pool, &expired.id).unwrap().is_active());
+    }
+
+    #[test]
+    fn it_revokes_every_session_of_a_user() {
+        let pool = setup_pool();
+        let (u, _) = setup_session(&pool, "rustler", Role::User);
+        let (other, _) = setup_session(&pool, "other", Role::User);
+        let other_session = create(&pool, &other.id, in_hours(1)).unwrap();
+        create(&pool, &u.id, in_hours(1)).unwrap();
+
+        // one more from setup_session
+        assert_eq!(revoke_all_for_user(&pool, &u.id).unwrap(), 2);
+        assert_eq!(revoke_all_for_user(&pool, &u.id).unwrap(), 0);
+        assert!(get_by_id(&pool, &other_session.id).unwrap().is_active());
+    }
+
+    #[test]
+    fn it_deletes_inactive_sessions() {
+        let pool = setup_pool();
+        let (u, _) = setup_session(&pool, "rustler", Role::User);
+        let active = create(&pool, &u.id, in_hours(1)).unwrap();
+        let expired = create(&pool, &u.id, in_hours(-1)).unwrap();
+        let revoked = create(&pool, &u.id, in_hours(1)).unwrap();
+        revoke(&pool, &revoked.id).unwrap();
+
+        assert_eq!(delete_inactive(&pool).unwrap(), 2);
+        assert!(get_by_id(&pool, &active.id).unwrap().is_active());
+        for s in &[expired, revoked] {
+            assert!(matches!(
+                get_by_id(&pool, &s.id),
+                Err(ApiError::NotFound(_))
+            ));
+        }
+        assert_eq!(delete_inactive(&pool).unwrap(), 0);
+    }
+}
@@ -1,10 +1,18 @@
 use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::{exists, not, sql};
 use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel::sqlite::Sqlite;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use crate::channel::{get_channel_id, Channel};
 use crate::database::DbPool;
 use crate::errors::ApiError;
+use crate::models::{banned_streams, user};
 use crate::schema::streams;
+use crate::service::ServiceChannel;
 
 #[derive(Debug, Queryable, Clone, Insertable, AsChangeset, Serialize, Deserialize)]
 pub struct Stream {
@@ -21,6 +29,7 @@ pub struct Stream {
     pub viewers: Option<i32>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub nsfw: bool,
 }
 
 impl Default for Stream {
@@ -39,24 +48,51 @@ impl Default for Stream {
             viewers: None,
             created_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc(),
+            nsfw: false,
+        }
+    }
+}
+
+impl Stream {
+    /// The channel the stream's id is derived from
+    pub fn to_channel(&self) -> Result<Channel, ApiError> {
+        Channel::new(
+            self.channel.clone(),
+            self.service.clone(),
+            self.path.clone().unwrap_or_default(),
+        )
+    }
+}
+
+/// Fail when a different channel already holds the id of `channel`
+pub fn check_collision(pool: &DbPool, channel: &Channel) -> Result<(), ApiError> {
+    let id = get_channel_id(channel) as i64;
+    match get_by_id(pool, id) {
+        Ok(existing) if existing.to_channel().ok().as_ref() != Some(channel) => {
+            Err(ApiError::StreamIdCollision(format!(
+                "{}/{} and {}/{} share id {}",
+                channel.service, channel.channel, existing.service, existing.channel, id
+            )))
         }
+        Ok(_) | Err(ApiError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e),
     }
 }
 
 pub fn insert(pool: &DbPool, mut stream: Stream) -> anyhow::Result<Stream, ApiError> {
     use crate::schema::streams::dsl::streams;
 
-    let conn = pool.get()?;
+    let channel = stream.to_channel()?;
 
-    let channel = Channel::new(
-        stream.channel.clone(),
-        stream.service.clone(),
-        stream.path.clone().unwrap_or(String::new()),
-    )?;
+    if let Some(ban) = banned_streams::find(pool, &channel.service, &channel.channel)? {
+        return Err(ApiError::StreamBanned(ban.reason_or_default()));
+    }
+    check_collision(pool, &channel)?;
 
     let id = get_channel_id(&channel);
     stream.id = Some(id as i64);
 
+    let conn = pool.get()?;
     diesel::insert_into(streams)
         .values(stream.clone())
         .execute(&conn)?;
@@ -69,22 +105,140 @@ pub fn update(pool: &DbPool, mut stream: Stream) -> anyhow::Result<(), ApiError>
     let conn = pool.get()?;
 
     if stream.id.is_none() {
-        let channel = Channel::new(
-            stream.channel.clone(),
-            stream.service.clone(),
-            stream.path.clone().unwrap_or(String::new()),
-        )?;
-
-        stream.id = Some(get_channel_id(&channel) as i64);
+        stream.id = Some(get_channel_id(&stream.to_channel()?) as i64);
     }
 
     diesel::update(streams)
-        .filter(id.eq(stream.id.clone()))
+        .filter(id.eq(stream.id))
         .set(stream)
         .execute(&conn)?;
     Ok(())
 }
 
+/// Set whether every rustler watching a stream is afk
+pub fn set_afk(pool: &DbPool, stream_id: i64, is_afk: bool) -> Result<(), ApiError> {
+    use crate::schema::streams::dsl::{afk, id, streams};
+
+    let conn = pool.get()?;
+
+    diesel::update(streams.filter(id.eq(stream_id)))
+        .set(afk.eq(is_afk))
+        .execute(&conn)?;
+    Ok(())
+}
+
+/// Copy a service channel's metadata onto a stream, returning whether it
+/// changed. The stream is left alone when the viewer count is unusable.
+pub fn apply_channel(stream: &mut Stream, channel: &dyn ServiceChannel) -> Result<bool, ApiError> {
+    let viewers = channel
+        .get_viewers()
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or_else(|| ApiError::SchemaValidation(String::from("invalid viewer count")))?;
+
+    let title = channel.get_title();
+    let thumbnail = Some(channel.get_thumbnail());
+    let live = Some(channel.get_live());
+    let viewers = Some(viewers);
+    let nsfw = channel.is_nsfw();
+
+    let changed = stream.title != title
+        || stream.thumbnail != thumbnail
+        || stream.live != live
+        || stream.viewers != viewers
+        || stream.nsfw != nsfw;
+
+    stream.title = title;
+    stream.thumbnail = thumbnail;
+    stream.live = live;
+    stream.viewers = viewers;
+    stream.nsfw = nsfw;
+    Ok(changed)
+}
+
+/// Fields a moderator may change on a stream, unset fields are left alone
+#[derive(Debug, Clone, AsChangeset)]
+#[table_name = "streams"]
+pub struct StreamModeration {
+    pub hidden: Option<bool>,
+    pub promoted: Option<bool>,
+    /// `Some(None)` clears the custom path
+    pub path: Option<Option<String>>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Default for StreamModeration {
+    fn default() -> Self {
+        Self {
+            hidden: None,
+            promoted: None,
+            path: None,
+            updated_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// Whether the custom path of `chn` is used by a stream on another channel or
+/// by a user other than `claimant`. Without a claimant, as when a moderator
+/// moves a stream, a user holding the path must be on the same channel.
+pub fn path_taken(pool: &DbPool, chn: &Channel, claimant: Option<&str>) -> Result<bool, ApiError> {
+    match user::get_by_stream_path(pool, &chn.stream_path) {
+        Ok(owner) if Some(owner.id.as_str()) == claimant => {}
+        Ok(owner)
+            if claimant.is_none()
+                && owner.service == chn.service
+                && owner.channel == chn.channel => {}
+        Ok(_) => return Ok(true),
+        Err(ApiError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(get_by_path(pool, &chn.stream_path)?
+        .iter()
+        .any(|s| s.service != chn.service || s.channel != chn.channel))
+}
+
+/// Apply moderator changes to a stream, returning the updated stream. A new
+/// path moves the stream to the id derived from it.
+pub fn moderate(
+    pool: &DbPool,
+    stream_id: i64,
+    changes: &StreamModeration,
+) -> Result<Stream, ApiError> {
+    use crate::schema::streams::dsl::{id, streams};
+
+    let current = get_by_id(pool, stream_id)?;
+    let new_id = match &changes.path {
+        Some(path) => {
+            let chn = Channel::new(
+                current.channel,
+                current.service,
+                path.clone().unwrap_or_default(),
+            )?;
+            if !chn.stream_path.is_empty() && path_taken(pool, &chn, None)? {
+                return Err(ApiError::StreamPathTaken(chn.stream_path));
+            }
+            let new_id = get_channel_id(&chn) as i64;
+            if new_id != stream_id && get_by_id(pool, new_id).is_ok() {
+                return Err(ApiError::StreamIdCollision(format!(
+                    "{}/{} already has a stream with id {}",
+                    chn.service, chn.channel, new_id
+                )));
+            }
+            new_id
+        }
+        None => stream_id,
+    };
+
+    let conn = pool.get()?;
+
+    diesel::update(streams.filter(id.eq(stream_id)))
+        .set((id.eq(new_id), changes))
+        .execute(&conn)?;
+
+    let stream = streams.filter(id.eq(new_id)).first::<Stream>(&conn)?;
+    Ok(stream)
+}
+
 pub fn get_by_id(pool: &DbPool, stream_id: i64) -> anyhow::Result<Stream, ApiError> {
     use crate::schema::streams::dsl::{id, streams};
 
@@ -98,11 +252,237 @@ pub fn get_by_id(pool: &DbPool, stream_id: i64) -> anyhow::Result<Stream, ApiErr
     Ok(stream)
 }
 
+pub fn get_by_ids(pool: &DbPool, stream_ids: &[i64]) -> Result<Vec<Stream>, ApiError> {
+    use crate::schema::streams::dsl::{id, streams};
+
+    let conn = pool.get()?;
+
+    let found = streams
+        .filter(id.eq_any(stream_ids))
+        .load::<Stream>(&conn)?;
+
+    Ok(found)
+}
+
+/// Narrows the public stream list, every unset field matches any stream
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct StreamFilter {
+    pub service: Option<String>,
+    pub live: Option<bool>,
+    pub nsfw: Option<bool>,
+}
+
+/// Streams that may be listed publicly, leaving out hidden streams and
+/// streams on a banned channel
+fn public_streams<'a>() -> streams::BoxedQuery<'a, Sqlite> {
+    use crate::schema::banned_streams::dsl as banned;
+    use crate::schema::streams::dsl::{channel, hidden, service, streams};
+
+    streams
+        .filter(hidden.is_null().or(hidden.eq(false)))
+        .filter(not(exists(
+            banned::banned_streams
+                .filter(banned::service.eq(service))
+                .filter(banned::channel.eq(channel)),
+        )))
+        .into_boxed()
+}
+
+/// The public streams with the given ids
+pub fn get_public_by_ids(pool: &DbPool, stream_ids: &[i64]) -> Result<Vec<Stream>, ApiError> {
+    use crate::schema::streams::dsl::id;
+
+    let conn = pool.get()?;
+
+    let found = public_streams()
+        .filter(id.eq_any(stream_ids))
+        .load::<Stream>(&conn)?;
+
+    Ok(found)
+}
+
+/// The public streams matching the filter
+fn filtered_public_streams<'a>(filter: &StreamFilter) -> streams::BoxedQuery<'a, Sqlite> {
+    use crate::schema::streams::dsl::{live, nsfw, service};
+
+    let mut query = public_streams();
+    if let Some(s) = &filter.service {
+        query = query.filter(service.eq(s.clone()));
+    }
+    query = match filter.live {
+        Some(true) => query.filter(live.eq(true)),
+        Some(false) => query.filter(live.is_null().or(live.eq(false))),
+        None => query,
+    };
+    if let Some(n) = filter.nsfw {
+        query = query.filter(nsfw.eq(n));
+    }
+    query
+}
+
+/// One page of the public streams matching the filter, promoted streams
+/// first, then by their live `rustlers` and then by viewers, along with the
+/// number of streams matching the filter
+pub fn get_public(
+    pool: &DbPool,
+    filter: &StreamFilter,
+    rustlers: &HashMap<i64, usize>,
+    limit: i64,
+    offset: i64,
+) -> Result<(i64, Vec<Stream>), ApiError> {
+    use crate::schema::streams::dsl::{id, promoted, viewers};
+
+    // rustler counts only live in memory, so they are sorted on as a literal
+    // mapping from stream id, made up of our own integers
+    let rustler_order = if rustlers.is_empty() {
+        String::from("0")
+    } else {
+        let cases: String = rustlers
+            .iter()
+            .map(|(stream_id, count)| format!(" WHEN {} THEN {}", stream_id, count))
+            .collect();
+        format!("CASE `streams`.`id`{} ELSE 0 END", cases)
+    };
+
+    let conn = pool.get()?;
+
+    let total = filtered_public_streams(filter)
+        .count()
+        .get_result::<i64>(&conn)?;
+    let found = filtered_public_streams(filter)
+        .order((
+            promoted.desc(),
+            sql::<BigInt>(&rustler_order).desc(),
+            viewers.desc(),
+            id.asc(),
+        ))
+        .limit(limit)
+        .offset(offset)
+        .load::<Stream>(&conn)?;
+
+    Ok((total, found))
+}
+
+/// Every stream for a channel, including those with a custom path
+pub fn get_by_channel(
+    pool: &DbPool,
+    stream_service: &str,
+    stream_channel: &str,
+) -> Result<Vec<Stream>, ApiError> {
+    use crate::schema::streams::dsl::{channel, service, streams};
+
+    let conn = pool.get()?;
+
+    let found = streams
+        .filter(service.eq(stream_service))
+        .filter(channel.eq(stream_channel))
+        .load::<Stream>(&conn)?;
+
+    Ok(found)
+}
+
+/// Every stream using a custom path
+pub fn get_by_path(pool: &DbPool, stream_path: &str) -> Result<Vec<Stream>, ApiError> {
+    use crate::schema::streams::dsl::{path, streams};
+
+    let conn = pool.get()?;
+
+    let found = streams.filter(path.eq(stream_path)).load::<Stream>(&conn)?;
+
+    Ok(found)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::helpers::setup_pool;
 
+    fn channel_stream(service: &str, channel: &str) -> Stream {
+        Stream {
+            service: String::from(service),
+            channel: String::from(channel),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_gives_every_channel_its_own_id() {
+        let pool = setup_pool();
+        for (service, channel) in &[
+            ("twitch", "jbpratt"),
+            ("twitch", "rustler"),
+            ("angelthump", "jbpratt"),
+            ("youtube", "jbpratt"),
+        ] {
+            insert(&pool, channel_stream(service, channel)).unwrap();
+        }
+
+        let conn = pool.get().unwrap();
+        let count: i64 = streams::table.count().get_result(&conn).unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn it_refuses_to_insert_a_colliding_id() {
+        use crate::schema::streams::dsl::id;
+
+        let pool = setup_pool();
+        let taken = insert(&pool, channel_stream("twitch", "jbpratt")).unwrap();
+        let other = channel_stream("twitch", "rustler");
+
+        // move the first stream onto the id of the second one
+        let other_id = get_channel_id(&other.to_channel().unwrap()) as i64;
+        diesel::update(streams::table.filter(id.eq(taken.id)))
+            .set(id.eq(other_id))
+            .execute(&pool.get().unwrap())
+            .unwrap();
+
+        assert!(matches!(
+            insert(&pool, other),
+            Err(ApiError::StreamIdCollision(_))
+        ));
+    }
+
+    #[test]
+    fn it_rekeys_streams_with_the_channel_id() {
+        use diesel::connection::SimpleConnection;
+
+        let pool = setup_pool();
+        let conn = pool.get().unwrap();
+        let streams = vec![
+            channel_stream("twitch", "jbpratt"),
+            Stream {
+                path: Some(String::from("jbpratt_irl")),
+                ..channel_stream("angelthump", "jbpratt")
+            },
+            channel_stream("m3u8", "https://example.com/live.m3u8"),
+        ];
+        for (i, stream) in streams.iter().enumerate() {
+            let stream = Stream {
+                id: Some(i as i64),
+                ..stream.clone()
+            };
+            diesel::insert_into(streams::table)
+                .values(stream)
+                .execute(&conn)
+                .unwrap();
+        }
+
+        conn.batch_execute(include_str!(
+            "../../migrations/2020-06-15-201807_rekey_streams/up.sql"
+        ))
+        .unwrap();
+
+        for stream in &streams {
+            let expected = get_channel_id(&stream.to_channel().unwrap()) as i64;
+            let found: Stream = streams::table
+                .filter(streams::id.eq(expected))
+                .first(&conn)
+                .unwrap();
+            assert_eq!(found.channel, stream.channel);
+        }
+    }
+
     #[test]
     fn it_inserts_a_stream() {
         let pool = setup_pool();
@@ -139,7 +519,7 @@ mod tests {
 
         let stream = result.unwrap();
 
-        let inserted_stream = get_by_id(&pool, stream.id.unwrap().clone());
+        let inserted_stream = get_by_id(&pool, stream.id.unwrap());
         assert!(inserted_stream.is_ok());
 
         let mut stream = inserted_stream.unwrap();
@@ -148,7 +528,7 @@ mod tests {
         let result = update(&pool, stream.clone());
         assert!(result.is_ok());
 
-        let updated_stream = get_by_id(&pool, stream.id.unwrap().clone());
+        let updated_stream = get_by_id(&pool, stream.id.unwrap());
         assert!(updated_stream.is_ok());
 
         assert_eq!(updated_stream.unwrap().viewers.unwrap(), 50);
@@ -160,4 +540,139 @@ mod tests {
         let stream = get_by_id(&pool, 1);
         assert!(stream.is_err());
     }
+
+    #[test]
+    fn it_inserts_and_finds_streams_by_ids() {
+        let pool = setup_pool();
+        let stream = Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+
+        let stream = insert(&pool, stream).unwrap();
+
+        let found = get_by_ids(&pool, &[stream.id.unwrap(), 1]);
+        assert!(found.is_ok());
+
+        let found = found.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].channel, stream.channel);
+    }
+
+    #[test]
+    fn it_finds_streams_by_channel() {
+        let pool = setup_pool();
+        for path in &[None, Some(String::from("jbpratt_irl"))] {
+            let stream = Stream {
+                service: String::from("twitch"),
+                channel: String::from("jbpratt"),
+                path: path.clone(),
+                ..Default::default()
+            };
+            insert(&pool, stream).unwrap();
+        }
+
+        let found = get_by_channel(&pool, "twitch", "jbpratt").unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(get_by_channel(&pool, "youtube", "jbpratt")
+            .unwrap()
+            .is_empty());
+
+        let found = get_by_path(&pool, "jbpratt_irl").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].channel, "jbpratt");
+    }
+
+    fn ban(pool: &DbPool, channel: &str) {
+        let ban = banned_streams::BannedStream {
+            channel: String::from(channel),
+            service: String::from("twitch"),
+            reason: Some(String::from("spam")),
+            ..Default::default()
+        };
+        banned_streams::insert(pool, &ban).unwrap();
+    }
+
+    #[test]
+    fn it_refuses_to_insert_a_banned_stream() {
+        let pool = setup_pool();
+        ban(&pool, "jbpratt");
+
+        let stream = Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+        let result = insert(&pool, stream);
+        assert_eq!(
+            result.unwrap_err(),
+            ApiError::StreamBanned(String::from("spam"))
+        );
+    }
+
+    #[test]
+    fn it_leaves_hidden_and_banned_streams_out_of_public_listings() {
+        let pool = setup_pool();
+        let mut ids = Vec::new();
+        for channel in &["visible", "banned"] {
+            let stream = Stream {
+                service: String::from("twitch"),
+                channel: String::from(*channel),
+                ..Default::default()
+            };
+            ids.push(insert(&pool, stream).unwrap().id.unwrap());
+        }
+        ban(&pool, "banned");
+
+        let found = get_public_by_ids(&pool, &ids).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].channel, "visible");
+
+        let mut hidden = found[0].clone();
+        hidden.hidden = Some(true);
+        update(&pool, hidden).unwrap();
+        assert!(get_public_by_ids(&pool, &ids).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_moderates_a_stream() {
+        let pool = setup_pool();
+        let stream = Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            path: Some(String::from("jbpratt")),
+            ..Default::default()
+        };
+        let stream_id = insert(&pool, stream).unwrap().id.unwrap();
+
+        let changes = StreamModeration {
+            hidden: Some(true),
+            promoted: Some(true),
+            ..Default::default()
+        };
+        let moderated = moderate(&pool, stream_id, &changes).unwrap();
+        assert_eq!(moderated.hidden, Some(true));
+        assert_eq!(moderated.promoted, Some(true));
+        assert_eq!(moderated.path.as_deref(), Some("jbpratt"));
+
+        let changes = StreamModeration {
+            path: Some(None),
+            ..Default::default()
+        };
+        let moderated = moderate(&pool, stream_id, &changes).unwrap();
+        assert_eq!(moderated.path, None);
+        assert_eq!(moderated.hidden, Some(true));
+
+        // the stream moved to the id of the channel without a path
+        let moved_id = moderated.id.unwrap();
+        assert_ne!(moved_id, stream_id);
+        assert!(matches!(
+            get_by_id(&pool, stream_id),
+            Err(ApiError::NotFound(_))
+        ));
+
+        let missing = moderate(&pool, stream_id, &changes);
+        assert!(matches!(missing, Err(ApiError::NotFound(_))));
+    }
 }
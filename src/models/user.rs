@@ -1,7 +1,17 @@
 use chrono::{NaiveDateTime, Utc};
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
 use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use regex::Regex;
 use uuid::Uuid;
 
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
 use crate::channel::Channel;
 use crate::database::DbPool;
 use crate::errors::ApiError;
@@ -22,19 +32,78 @@ pub struct User {
     pub ban_reason: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
-    pub is_admin: Option<bool>,
+    pub role: Role,
+}
+
+/// What a user may moderate, ordered from least to most privileged
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow, Serialize,
+)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(ApiError::InvalidRole(s.to_string())),
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for Role {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        <str as ToSql<Text, Sqlite>>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Role {
+    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        let role = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(role.parse()?)
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^[a-zA-Z0-9_]{3,32}$").unwrap();
+    }
+    RE.is_match(name)
 }
 
 pub fn get_by_id(pool: &DbPool, uid: Uuid) -> anyhow::Result<User, ApiError> {
     use crate::schema::users::dsl::id;
 
     let conn = pool.get()?;
-    Ok(users::table
+    users::table
         .filter(id.eq(uid.to_string()))
         .first::<User>(&conn)
-        .map_err(|_| {
-            ApiError::NotFound(format!("failed to find user with id: {}", uid.to_string()))
-        })?)
+        .map_err(|_| ApiError::NotFound(format!("failed to find user with id: {}", uid)))
 }
 
 pub fn get_by_twitch_id(pool: &DbPool, tid: i64) -> anyhow::Result<User, ApiError> {
@@ -42,15 +111,10 @@ pub fn get_by_twitch_id(pool: &DbPool, tid: i64) -> anyhow::Result<User, ApiErro
 
     let conn = pool.get()?;
 
-    Ok(users::table
+    users::table
         .filter(twitch_id.eq(tid))
         .first::<User>(&conn)
-        .map_err(|_| {
-            ApiError::NotFound(format!(
-                "failed to find user with twitch_id: {}",
-                tid.to_string()
-            ))
-        })?)
+        .map_err(|_| ApiError::NotFound(format!("failed to find user with twitch_id: {}", tid)))
 }
 
 pub fn get_by_name(pool: &DbPool, user_name: &str) -> anyhow::Result<User, ApiError> {
@@ -58,15 +122,10 @@ pub fn get_by_name(pool: &DbPool, user_name: &str) -> anyhow::Result<User, ApiEr
 
     let conn = pool.get()?;
 
-    Ok(users::table
+    users::table
         .filter(name.eq(user_name))
         .first::<User>(&conn)
-        .map_err(|_| {
-            ApiError::NotFound(format!(
-                "failed to find user with name: {}",
-                user_name.to_string()
-            ))
-        })?)
+        .map_err(|_| ApiError::NotFound(format!("failed to find user with name: {}", user_name)))
 }
 
 pub fn get_by_stream_path(pool: &DbPool, user_stream_path: &str) -> anyhow::Result<User, ApiError> {
@@ -74,15 +133,15 @@ pub fn get_by_stream_path(pool: &DbPool, user_stream_path: &str) -> anyhow::Resu
 
     let conn = pool.get()?;
 
-    Ok(users::table
+    users::table
         .filter(stream_path.eq(user_stream_path))
         .first::<User>(&conn)
         .map_err(|_| {
             ApiError::NotFound(format!(
                 "failed to find user with stream_path: {}",
-                user_stream_path.to_string()
+                user_stream_path
             ))
-        })?)
+        })
 }
 
 pub fn create(
@@ -107,7 +166,6 @@ pub fn create(
         name: name.to_string(),
         last_ip: ip.to_string(),
         left_chat: None,
-        is_admin: None,
         channel: chn.channel,
         service: chn.service,
         stream_path,
@@ -140,6 +198,49 @@ pub fn update(pool: &DbPool, update_user: &User) -> anyhow::Result<(), ApiError>
     Ok(())
 }
 
+pub fn rename(pool: &DbPool, user_id: &str, new_name: &str) -> Result<User, ApiError> {
+    use crate::schema::users::dsl::{id, name, updated_at};
+
+    if !valid_name(new_name) {
+        return Err(ApiError::InvalidName(new_name.to_string()));
+    }
+
+    let conn = pool.get()?;
+    let updated = diesel::update(users::table.filter(id.eq(user_id)))
+        .set((name.eq(new_name), updated_at.eq(Utc::now().naive_utc())))
+        .execute(&conn)?;
+    if updated == 0 {
+        return Err(ApiError::NotFound(format!(
+            "failed to find user with id: {}",
+            user_id
+        )));
+    }
+
+    Ok(users::table.filter(id.eq(user_id)).first::<User>(&conn)?)
+}
+
+/// Ban a user for the given reason, or lift their ban when `reason` is `None`
+pub fn set_banned(pool: &DbPool, user_id: &str, reason: Option<&str>) -> Result<User, ApiError> {
+    use crate::schema::users::dsl::{ban_reason, id, is_banned, updated_at};
+
+    let conn = pool.get()?;
+    let updated = diesel::update(users::table.filter(id.eq(user_id)))
+        .set((
+            is_banned.eq(reason.is_some()),
+            ban_reason.eq(reason),
+            updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(&conn)?;
+    if updated == 0 {
+        return Err(ApiError::NotFound(format!(
+            "failed to find user with id: {}",
+            user_id
+        )));
+    }
+
+    Ok(users::table.filter(id.eq(user_id)).first::<User>(&conn)?)
+}
+
 impl Default for User {
     fn default() -> Self {
         Self {
@@ -155,8 +256,8 @@ impl Default for User {
             last_seen: Utc::now().naive_utc(),
             ban_reason: None,
             left_chat: None,
-            is_admin: None,
             is_banned: false,
+            role: Role::User,
         }
     }
 }
@@ -168,7 +269,7 @@ mod tests {
 
     fn create_test_user(pool: &DbPool) -> anyhow::Result<User, ApiError> {
         create(
-            &pool,
+            pool,
             8,
             Channel {
                 channel: String::from("jbpratt"),
@@ -255,13 +356,62 @@ mod tests {
 
         assert!(new_user.is_ok());
         let mut unwrapped = new_user.unwrap();
-        unwrapped.is_admin = Some(true);
+        unwrapped.left_chat = Some(true);
 
         let res = update(&pool, &unwrapped);
         assert!(res.is_ok());
 
         let id = Uuid::parse_str(unwrapped.id.as_str()).unwrap();
         let found_user = get_by_id(&pool, id).unwrap();
-        assert!(found_user.is_admin.unwrap());
+        assert_eq!(found_user.left_chat, Some(true));
+    }
+
+    #[test]
+    fn it_persists_a_users_role() {
+        let pool = setup_pool();
+        let mut user = create_test_user(&pool).unwrap();
+        assert_eq!(user.role, Role::User);
+
+        user.role = Role::Moderator;
+        update(&pool, &user).unwrap();
+
+        let found_user = get_by_twitch_id(&pool, 8).unwrap();
+        assert_eq!(found_user.role, Role::Moderator);
+        assert!(Role::Admin > found_user.role);
+        assert!("owner".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn it_renames_a_user() {
+        let pool = setup_pool();
+        let user = create_test_user(&pool).unwrap();
+
+        let renamed = rename(&pool, &user.id, "jbpratt_2").unwrap();
+        assert_eq!(renamed.name, "jbpratt_2");
+        assert!(get_by_name(&pool, "jbpratt_2").is_ok());
+
+        let invalid = rename(&pool, &user.id, "no spaces");
+        assert_eq!(
+            invalid.unwrap_err(),
+            ApiError::InvalidName(String::from("no spaces"))
+        );
+        assert!(matches!(
+            rename(&pool, "missing", "jbpratt_3"),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn it_bans_and_unbans_a_user() {
+        let pool = setup_pool();
+        let user = create_test_user(&pool).unwrap();
+
+        let banned = set_banned(&pool, &user.id, Some("spam")).unwrap();
+        assert!(banned.is_banned);
+        assert_eq!(banned.ban_reason.as_deref(), Some("spam"));
+
+        let unbanned = set_banned(&pool, &user.id, None).unwrap();
+        assert!(!unbanned.is_banned);
+        assert_eq!(unbanned.ban_reason, None);
     }
 }
@@ -0,0 +1,264 @@
+use actix::prelude::*;
+use chrono::Utc;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::models::stream::{self, apply_channel};
+use crate::registry::{GetAllCounts, StreamRegistry, StreamUpdated};
+use crate::service::ServiceChannel;
+use crate::state::AppState;
+
+/// How often streams with rustlers are refreshed from their services
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// How many lookups in a row may fail before a stream is marked offline
+const MAX_FAILURES: u32 = 3;
+
+type Lookup = (stream::Stream, anyhow::Result<Box<dyn ServiceChannel>>);
+
+/// Background actor refreshing stream metadata from the platform APIs
+pub struct StreamPoller {
+    db: DbPool,
+    registry: Addr<StreamRegistry>,
+    state: AppState,
+    // consecutive failed lookups per stream
+    failures: HashMap<i64, u32>,
+    polling: bool,
+}
+
+impl StreamPoller {
+    pub fn new(pool: DbPool, registry: Addr<StreamRegistry>, state: AppState) -> Self {
+        Self {
+            db: pool,
+            registry,
+            state,
+            failures: HashMap::new(),
+            polling: false,
+        }
+    }
+
+    fn poll(&mut self, ctx: &mut Context<Self>) {
+        if self.polling {
+            return;
+        }
+        self.polling = true;
+
+        let db = self.db.clone();
+        let registry = self.registry.clone();
+        let state = self.state.clone();
+
+        let fut = async move {
+            let counts = registry.send(GetAllCounts).await.unwrap_or_default();
+            let ids: Vec<i64> = counts.keys().copied().collect();
+
+            let streams = match stream::get_by_ids(&db, &ids) {
+                Ok(streams) => streams,
+                Err(e) => {
+                    log::error!("failed to load streams to poll: {}", e);
+                    return Vec::new();
+                }
+            };
+
+            let mut lookups: Vec<Lookup> = Vec::new();
+            for s in streams {
+                if let Some(res) = state.services.get_channel(&s.service, &s.channel).await {
+                    lookups.push((s, res));
+                }
+            }
+            lookups
+        };
+
+        ctx.spawn(fut.into_actor(self).map(|lookups, act, _| {
+            act.apply_lookups(lookups);
+            act.polling = false;
+        }));
+    }
+
+    /// Write back every lookup of a poll, forgetting the failures of streams
+    /// that were not polled
+    fn apply_lookups(&mut self, lookups: Vec<Lookup>) {
+        let polled: HashSet<i64> = lookups.iter().filter_map(|(s, _)| s.id).collect();
+        self.failures.retain(|id, _| polled.contains(id));
+
+        for (s, res) in lookups {
+            if let Err(e) = self.apply_lookup(s, res) {
+                log::error!("failed to update polled stream: {}", e);
+            }
+        }
+    }
+
+    /// Write the result of a service lookup back to the stream
+    fn apply_lookup(
+        &mut self,
+        mut s: stream::Stream,
+        res: anyhow::Result<Box<dyn ServiceChannel>>,
+    ) -> Result<(), ApiError> {
+        let stream_id = match s.id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let applied = res
+            .map_err(ApiError::from)
+            .and_then(|channel| apply_channel(&mut s, &*channel));
+        let changed = match applied {
+            Ok(changed) => {
+                self.failures.remove(&stream_id);
+                changed
+            }
+            Err(e) => {
+                log::warn!("failed to poll {}/{}: {}", s.service, s.channel, e);
+                let failures = self.failures.entry(stream_id).or_insert(0);
+                *failures += 1;
+                if *failures >= MAX_FAILURES && s.live != Some(false) {
+                    s.live = Some(false);
+                    s.viewers = Some(0);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        if changed {
+            s.updated_at = Utc::now().naive_utc();
+            stream::update(&self.db, s)?;
+            self.registry.do_send(StreamUpdated { stream_id });
+        }
+        Ok(())
+    }
+}
+
+impl Actor for StreamPoller {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(POLL_INTERVAL, |act, ctx| act.poll(ctx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::setup_pool;
+
+    use anyhow::anyhow;
+    use std::sync::Arc;
+
+    /// a channel reporting the given viewer count
+    struct TestChannel(Option<u32>);
+
+    impl ServiceChannel for TestChannel {
+        fn get_live(&self) -> bool {
+            true
+        }
+        fn is_nsfw(&self) -> bool {
+            true
+        }
+        fn get_title(&self) -> String {
+            String::from("rustling")
+        }
+        fn get_thumbnail(&self) -> String {
+            String::from("https://example.com/thumb.jpg")
+        }
+        fn get_viewers(&self) -> Option<u32> {
+            self.0
+        }
+    }
+
+    fn test_poller(pool: DbPool) -> StreamPoller {
+        let state = AppState::new(Arc::new(reqwest::Client::new()));
+        let registry = StreamRegistry::new(pool.clone()).start();
+        StreamPoller::new(pool, registry, state)
+    }
+
+    fn insert_stream(pool: &DbPool) -> stream::Stream {
+        let s = stream::Stream {
+            service: String::from("youtube"),
+            channel: String::from("jbpratt"),
+            live: Some(true),
+            viewers: Some(5),
+            ..Default::default()
+        };
+        stream::insert(pool, s).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn it_writes_polled_metadata() {
+        let pool = setup_pool();
+        let s = insert_stream(&pool);
+        let mut poller = test_poller(pool.clone());
+
+        let res = poller.apply_lookup(s.clone(), Ok(Box::new(TestChannel(Some(42)))));
+        assert!(res.is_ok());
+
+        let updated = stream::get_by_id(&pool, s.id.unwrap()).unwrap();
+        assert_eq!(updated.title, "rustling");
+        assert_eq!(updated.viewers, Some(42));
+        assert_eq!(updated.live, Some(true));
+        assert!(updated.nsfw);
+    }
+
+    #[actix_rt::test]
+    async fn it_marks_a_stream_offline_after_failures() {
+        let pool = setup_pool();
+        let s = insert_stream(&pool);
+        let mut poller = test_poller(pool.clone());
+
+        for _ in 0..MAX_FAILURES - 1 {
+            let _ = poller.apply_lookup(s.clone(), Err(anyhow!("timed out")));
+        }
+        let still_live = stream::get_by_id(&pool, s.id.unwrap()).unwrap();
+        assert_eq!(still_live.live, Some(true));
+
+        let _ = poller.apply_lookup(s.clone(), Err(anyhow!("timed out")));
+        let offline = stream::get_by_id(&pool, s.id.unwrap()).unwrap();
+        assert_eq!(offline.live, Some(false));
+        assert_eq!(offline.viewers, Some(0));
+    }
+
+    #[actix_rt::test]
+    async fn it_resets_failures_after_a_successful_poll() {
+        let pool = setup_pool();
+        let s = insert_stream(&pool);
+        let mut poller = test_poller(pool.clone());
+
+        let _ = poller.apply_lookup(s.clone(), Err(anyhow!("timed out")));
+        let _ = poller.apply_lookup(s.clone(), Ok(Box::new(TestChannel(Some(42)))));
+        assert!(poller.failures.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn it_counts_an_invalid_viewer_count_as_a_failure() {
+        let pool = setup_pool();
+        let s = insert_stream(&pool);
+        let mut poller = test_poller(pool.clone());
+
+        for _ in 0..MAX_FAILURES {
+            let res = poller.apply_lookup(s.clone(), Ok(Box::new(TestChannel(None))));
+            assert!(res.is_ok());
+        }
+        let offline = stream::get_by_id(&pool, s.id.unwrap()).unwrap();
+        assert_eq!(offline.live, Some(false));
+        assert_eq!(offline.title, "");
+
+        let huge = poller.apply_lookup(s.clone(), Ok(Box::new(TestChannel(Some(u32::MAX)))));
+        assert!(huge.is_ok());
+        assert_eq!(poller.failures[&s.id.unwrap()], MAX_FAILURES + 1);
+    }
+
+    #[actix_rt::test]
+    async fn it_forgets_failures_of_streams_no_longer_polled() {
+        let pool = setup_pool();
+        let s = insert_stream(&pool);
+        let mut poller = test_poller(pool.clone());
+
+        poller.apply_lookups(vec![(s.clone(), Err(anyhow!("timed out")))]);
+        assert_eq!(poller.failures.len(), 1);
+
+        poller.apply_lookups(Vec::new());
+        assert!(poller.failures.is_empty());
+    }
+}
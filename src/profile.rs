@@ -0,0 +1,263 @@
+use actix_web::web::{self, Json};
+use actix_web::{Error, HttpResponse};
+use chrono::Utc;
+
+use std::collections::BTreeMap;
+
+use crate::channel::{normalize_channel, valid_service, valid_stream_path, Channel};
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::helpers::respond_json;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::{stream, user};
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("")
+            .route(web::get().to(get_profile))
+            .route(web::put().to(update_profile)),
+    );
+}
+
+/// The channel settings a user can see and edit. An empty `stream_path`
+/// means the user has no custom path.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    name: String,
+    service: String,
+    channel: String,
+    #[serde(default)]
+    stream_path: String,
+}
+
+impl Profile {
+    fn new(u: &user::User) -> Self {
+        // users without a custom path carry the default "/service/channel"
+        let stream_path = if valid_stream_path(&u.stream_path) {
+            u.stream_path.clone()
+        } else {
+            String::new()
+        };
+
+        Self {
+            name: u.name.clone(),
+            service: u.service.clone(),
+            channel: u.channel.clone(),
+            stream_path,
+        }
+    }
+}
+
+/// Validation errors keyed by the field they apply to
+#[derive(Debug, Default, Serialize)]
+struct FieldErrors {
+    errors: BTreeMap<&'static str, String>,
+}
+
+impl FieldErrors {
+    fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.errors.entry(field).or_insert_with(|| message.into());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Check every field on its own so each error can be reported against it
+fn validate(profile: &Profile) -> Result<Channel, FieldErrors> {
+    let mut errors = FieldErrors::default();
+
+    if !valid_service(&profile.service) {
+        errors.add("service", format!("invalid service: {}", profile.service));
+    } else if let Err(e) = normalize_channel(&profile.service, &profile.channel) {
+        errors.add("channel", e.to_string());
+    }
+    if !valid_stream_path(&profile.stream_path) {
+        errors.add(
+            "stream_path",
+            "must be 3 to 32 lowercase letters, digits or underscores",
+        );
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Channel::new(
+        profile.channel.clone(),
+        profile.service.clone(),
+        profile.stream_path.clone(),
+    )
+    .map_err(|e| {
+        errors.add("channel", e.to_string());
+        errors
+    })
+}
+
+async fn get_profile(auth: AuthenticatedUser) -> Result<Json<Profile>, ApiError> {
+    respond_json(Profile::new(&auth.user))
+}
+
+/// Change the channel and custom stream path of the logged in user
+async fn update_profile(
+    auth: AuthenticatedUser,
+    body: web::Json<Profile>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let chn = match validate(&body) {
+        Ok(chn) => chn,
+        Err(errors) => return Ok(HttpResponse::BadRequest().json(errors)),
+    };
+
+    let mut u = auth.user;
+    if stream::path_taken(&pool, &chn, Some(&u.id))? {
+        let mut errors = FieldErrors::default();
+        errors.add("stream_path", "already taken");
+        return Ok(HttpResponse::Conflict().json(errors));
+    }
+
+    u.service = chn.service;
+    u.channel = chn.channel;
+    u.stream_path = chn.stream_path;
+    u.updated_at = Utc::now().naive_utc();
+    user::update(&pool, &u)?;
+    log::info!(
+        "{} changed their channel to {}/{}",
+        u.name,
+        u.service,
+        u.channel
+    );
+
+    Ok(HttpResponse::Ok().json(Profile::new(&u)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::middleware::auth::session_cookie;
+    use crate::models::user::Role;
+    use crate::routes::routes;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::{json, Value};
+
+    fn authed(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.cookie(session_cookie(token.to_string()))
+    }
+
+    async fn read_json(resp: ServiceResponse) -> Value {
+        serde_json::from_slice(&test::read_body(resp).await).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn it_shows_and_edits_a_profile() {
+        let pool = setup_pool();
+        let (u, token) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+
+        let req = test::TestRequest::get().uri("/api/profile");
+        let profile =
+            read_json(test::call_service(&mut app, authed(req, &token).to_request()).await).await;
+        assert_eq!(profile["name"], "rustler");
+        assert_eq!(profile["stream_path"], "");
+
+        let req = test::TestRequest::put()
+            .uri("/api/profile")
+            .set_json(&json!({
+                "service": "angelthump",
+                "channel": "jbpratt",
+                "stream_path": "jbpratt_irl",
+            }));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let profile = read_json(resp).await;
+        assert_eq!(profile["stream_path"], "jbpratt_irl");
+
+        let found = user::get_by_stream_path(&pool, "jbpratt_irl").unwrap();
+        assert_eq!(found.id, u.id);
+        assert_eq!(found.service, "angelthump");
+
+        // dropping the custom path falls back to the default one
+        let req = test::TestRequest::put()
+            .uri("/api/profile")
+            .set_json(&json!({
+                "service": "angelthump",
+                "channel": "jbpratt",
+            }));
+        let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let found = user::get_by_id(&pool, u.id.parse().unwrap()).unwrap();
+        assert_eq!(found.stream_path, "/angelthump/jbpratt");
+
+        let req = test::TestRequest::get().uri("/api/profile").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_errors_per_field() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool).configure(routes)).await;
+
+        let cases = [
+            (
+                json!({"service": "chaturbate", "channel": "jbpratt", "stream_path": "No"}),
+                vec!["service", "stream_path"],
+            ),
+            (
+                json!({"service": "twitch", "channel": "not a channel"}),
+                vec!["channel"],
+            ),
+            (
+                json!({"service": "m3u8", "channel": "ftp://example.com/live.m3u8"}),
+                vec!["channel"],
+            ),
+        ];
+        for (profile, fields) in cases.iter() {
+            let req = test::TestRequest::put()
+                .uri("/api/profile")
+                .set_json(profile);
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", profile);
+
+            let body = read_json(resp).await;
+            let found: Vec<&String> = body["errors"].as_object().unwrap().keys().collect();
+            assert_eq!(&found, fields, "{}", profile);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_refuses_a_stream_path_in_use() {
+        let pool = setup_pool();
+        let (mut other, _) = setup_session(&pool, "other", Role::User);
+        other.stream_path = String::from("taken");
+        user::update(&pool, &other).unwrap();
+        let stream = stream::Stream {
+            service: String::from("twitch"),
+            channel: String::from("someone"),
+            path: Some(String::from("streamed")),
+            ..Default::default()
+        };
+        stream::insert(&pool, stream).unwrap();
+
+        let (_, token) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool).configure(routes)).await;
+
+        for path in &["taken", "streamed"] {
+            let req = test::TestRequest::put()
+                .uri("/api/profile")
+                .set_json(&json!({
+                    "service": "twitch",
+                    "channel": "rustler",
+                    "stream_path": path,
+                }));
+            let resp = test::call_service(&mut app, authed(req, &token).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT, "{}", path);
+            let body = read_json(resp).await;
+            assert_eq!(body["errors"]["stream_path"], "already taken");
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use serde_json::Value;
+
+use std::fmt;
+
+use crate::errors::ApiError;
+use crate::models::stream;
+use crate::registry::LiveStream;
+
+/// Names of every command a client may send
+pub const COMMANDS: &[&str] = &["setStream", "setAfk", "getStream"];
+
+/// A command sent by a websocket client, encoded as `["command", args...]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientCommand {
+    /// `["setStream", channel, service]`
+    SetStream { channel: String, service: String },
+    /// `["setStream", path]`
+    SetStreamPath(String),
+    /// `["setStream", null]`
+    ClearStream,
+    /// `["setAfk", bool]`
+    SetAfk(bool),
+    /// `["getStream"]`
+    GetStream,
+}
+
+impl ClientCommand {
+    /// Parse a text frame into a command, or the error frame to reply with
+    pub fn parse(text: &str) -> Result<Self, CommandError> {
+        let raw: Vec<Value> = serde_json::from_str(text)
+            .map_err(|e| CommandError::new(ErrorCode::MalformedCommand, e.to_string()))?;
+
+        match raw.first() {
+            Some(Value::String(name)) if COMMANDS.contains(&name.as_str()) => {}
+            Some(Value::String(name)) => {
+                return Err(CommandError::new(
+                    ErrorCode::UnknownCommand,
+                    format!("unknown command: {}", name),
+                ))
+            }
+            _ => {
+                return Err(CommandError::new(
+                    ErrorCode::MalformedCommand,
+                    "expected a command name",
+                ))
+            }
+        }
+
+        serde_json::from_value(Value::Array(raw))
+            .map_err(|e| CommandError::new(ErrorCode::MalformedCommand, e.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientCommand {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CommandVisitor;
+
+        impl<'de> Visitor<'de> for CommandVisitor {
+            type Value = ClientCommand;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(r#"a ["command", args...] array"#)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<ClientCommand, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let name: String = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+                let cmd = match name.as_str() {
+                    "setStream" => {
+                        let first: Option<String> = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                        match first {
+                            None => ClientCommand::ClearStream,
+                            Some(first) => match seq.next_element::<String>()? {
+                                Some(service) => ClientCommand::SetStream {
+                                    channel: first,
+                                    service,
+                                },
+                                None => ClientCommand::SetStreamPath(first),
+                            },
+                        }
+                    }
+                    "setAfk" => ClientCommand::SetAfk(
+                        seq.next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(1, &self))?,
+                    ),
+                    "getStream" => ClientCommand::GetStream,
+                    _ => return Err(de::Error::unknown_variant(&name, COMMANDS)),
+                };
+
+                if seq.next_element::<IgnoredAny>()?.is_some() {
+                    return Err(de::Error::custom(format!(
+                        "too many arguments for {}",
+                        name
+                    )));
+                }
+
+                Ok(cmd)
+            }
+        }
+
+        deserializer.deserialize_seq(CommandVisitor)
+    }
+}
+
+/// Stable codes sent in `["ERR", code, message]` frames
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    UnknownCommand,
+    MalformedCommand,
+    StreamNotSet,
+    InvalidStream,
+    NotFound,
+    Internal,
+}
+
+/// A failed command, sent back to the client as an error frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ApiError> for CommandError {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::ChannelValidation(_)
+            | ApiError::ChannelNormalization(_)
+            | ApiError::InvalidService(_) => Self::new(ErrorCode::InvalidStream, e.to_string()),
+            ApiError::NotFound(msg) => Self::new(ErrorCode::NotFound, msg),
+            _ => {
+                log::error!("websocket command failed: {}", e);
+                Self::new(ErrorCode::Internal, "internal error")
+            }
+        }
+    }
+}
+
+/// An event sent to websocket clients, encoded as `["EVENT", args...]`
+#[derive(Debug)]
+pub enum ServerEvent {
+    StreamSet(Option<stream::Stream>),
+    StreamGet(LiveStream),
+    AfkSet(bool),
+    StreamsSet(Vec<LiveStream>),
+    StreamUpdated(LiveStream),
+    RustlersSet(i64, usize),
+    StreamRemoved(i64),
+    StreamBanned(String),
+    SessionEnded(String),
+    Err(CommandError),
+}
+
+impl ServerEvent {
+    /// Encode the event as the body of a text frame
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("server events always serialize")
+    }
+}
+
+impl From<CommandError> for ServerEvent {
+    fn from(e: CommandError) -> Self {
+        ServerEvent::Err(e)
+    }
+}
+
+impl Serialize for ServerEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ServerEvent::StreamSet(stream) => ("STREAM_SET", stream).serialize(serializer),
+            ServerEvent::StreamGet(stream) => ("STREAM_GET", stream).serialize(serializer),
+            ServerEvent::AfkSet(afk) => ("AFK_SET", afk).serialize(serializer),
+            ServerEvent::StreamsSet(streams) => ("STREAMS_SET", streams).serialize(serializer),
+            ServerEvent::StreamUpdated(stream) => ("STREAM_UPDATED", stream).serialize(serializer),
+            ServerEvent::RustlersSet(id, rustlers) => {
+                ("RUSTLERS_SET", id, rustlers).serialize(serializer)
+            }
+            ServerEvent::StreamRemoved(id) => ("STREAM_REMOVED", id).serialize(serializer),
+            ServerEvent::StreamBanned(reason) => ("STREAM_BANNED", reason).serialize(serializer),
+            ServerEvent::SessionEnded(reason) => ("SESSION_ENDED", reason).serialize(serializer),
+            ServerEvent::Err(e) => ("ERR", e.code, &e.message).serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_set_stream_commands() {
+        assert_eq!(
+            ClientCommand::parse(r#"["setStream", "jbpratt", "twitch"]"#),
+            Ok(ClientCommand::SetStream {
+                channel: String::from("jbpratt"),
+                service: String::from("twitch"),
+            })
+        );
+        assert_eq!(
+            ClientCommand::parse(r#"["setStream", "jbpratt"]"#),
+            Ok(ClientCommand::SetStreamPath(String::from("jbpratt")))
+        );
+        assert_eq!(
+            ClientCommand::parse(r#"["setStream", null]"#),
+            Ok(ClientCommand::ClearStream)
+        );
+    }
+
+    #[test]
+    fn it_parses_set_afk_and_get_stream() {
+        assert_eq!(
+            ClientCommand::parse(r#"["setAfk", true]"#),
+            Ok(ClientCommand::SetAfk(true))
+        );
+        assert_eq!(
+            ClientCommand::parse(r#"["getStream"]"#),
+            Ok(ClientCommand::GetStream)
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_commands() {
+        let err = ClientCommand::parse(r#"["setChannel", "jbpratt"]"#).unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnknownCommand);
+    }
+
+    #[test]
+    fn it_rejects_malformed_commands() {
+        for text in &[
+            "not json",
+            "{}",
+            "[]",
+            "[1, 2]",
+            r#"["setStream"]"#,
+            r#"["setAfk", "yes"]"#,
+            r#"["getStream", 1]"#,
+        ] {
+            let err = ClientCommand::parse(text).unwrap_err();
+            assert_eq!(err.code, ErrorCode::MalformedCommand, "{}", text);
+        }
+    }
+
+    #[test]
+    fn it_serializes_server_events() {
+        assert_eq!(ServerEvent::AfkSet(true).to_text(), r#"["AFK_SET",true]"#);
+        assert_eq!(
+            ServerEvent::StreamSet(None).to_text(),
+            r#"["STREAM_SET",null]"#
+        );
+        assert_eq!(
+            ServerEvent::RustlersSet(5, 2).to_text(),
+            r#"["RUSTLERS_SET",5,2]"#
+        );
+        assert_eq!(
+            ServerEvent::from(CommandError::new(ErrorCode::StreamNotSet, "no stream set"))
+                .to_text(),
+            r#"["ERR","STREAM_NOT_SET","no stream set"]"#
+        );
+    }
+}
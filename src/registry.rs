@@ -0,0 +1,974 @@
+use actix::prelude::*;
+use uuid::Uuid;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::models::stream;
+use crate::protocol::ServerEvent;
+
+/// How often pending stream changes are pushed to subscribers
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// live audience numbers for a single stream
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counts {
+    pub rustlers: usize,
+    /// kept apart from the stream's own `afk` flag once flattened
+    #[serde(rename = "afk_rustlers")]
+    pub afk: usize,
+}
+
+/// a stream record merged with its live counts, as sent to clients
+#[derive(Clone, Debug, Serialize)]
+pub struct LiveStream {
+    #[serde(flatten)]
+    pub stream: stream::Stream,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// state of a websocket session as seen by the registry
+#[derive(Clone, Copy, Debug)]
+struct Session {
+    stream_id: i64,
+    afk: bool,
+}
+
+/// how the registry reaches a connected websocket session
+struct Subscriber {
+    broadcast: Recipient<Broadcast>,
+    kick: Recipient<Kicked>,
+    moved: Recipient<Moved>,
+    terminate: Recipient<Terminated>,
+    /// the logged in user, `None` for anonymous viewers
+    user_id: Option<String>,
+}
+
+/// Shared actor tracking which stream every websocket session is watching
+/// and pushing changes to the stream list out to every subscriber.
+pub struct StreamRegistry {
+    db: DbPool,
+    sessions: HashMap<Uuid, Session>,
+    streams: HashMap<i64, Counts>,
+    subscribers: HashMap<Uuid, Subscriber>,
+    // streams subscribers currently know about
+    announced: HashSet<i64>,
+    // streams with changed counts since the last broadcast
+    dirty: HashSet<i64>,
+    // streams with changed metadata since the last broadcast
+    updated: HashSet<i64>,
+    // streams with changed afk counts since the last broadcast
+    afk_changed: HashSet<i64>,
+    // the afk flag last written to each watched stream's row
+    afk_flags: HashMap<i64, bool>,
+}
+
+impl StreamRegistry {
+    pub fn new(pool: DbPool) -> Self {
+        Self {
+            db: pool,
+            sessions: HashMap::new(),
+            streams: HashMap::new(),
+            subscribers: HashMap::new(),
+            announced: HashSet::new(),
+            dirty: HashSet::new(),
+            updated: HashSet::new(),
+            afk_changed: HashSet::new(),
+            afk_flags: HashMap::new(),
+        }
+    }
+
+    fn remove_session(&mut self, id: &Uuid) {
+        let session = match self.sessions.remove(id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        if let Some(counts) = self.streams.get_mut(&session.stream_id) {
+            counts.rustlers -= 1;
+            if session.afk {
+                counts.afk -= 1;
+            }
+            if counts.rustlers == 0 {
+                self.streams.remove(&session.stream_id);
+            }
+        }
+        self.dirty.insert(session.stream_id);
+    }
+
+    /// Mark a stream as afk in the database when every rustler watching it is
+    /// afk, only writing when the flag flips
+    fn sync_afk(&mut self, stream_id: i64) -> Result<(), ApiError> {
+        let afk = matches!(self.streams.get(&stream_id), Some(c) if c.afk == c.rustlers);
+        let written = self.afk_flags.get(&stream_id).copied();
+
+        if !self.streams.contains_key(&stream_id) {
+            self.afk_flags.remove(&stream_id);
+            if written == Some(true) {
+                stream::set_afk(&self.db, stream_id, false)?;
+            }
+            return Ok(());
+        }
+        if written != Some(afk) {
+            stream::set_afk(&self.db, stream_id, afk)?;
+        }
+        self.afk_flags.insert(stream_id, afk);
+        Ok(())
+    }
+
+    /// load the public streams for the given ids merged with their counts
+    fn live_streams(&self, ids: &[i64]) -> Result<Vec<LiveStream>, ApiError> {
+        Ok(stream::get_public_by_ids(&self.db, ids)?
+            .into_iter()
+            .filter_map(|s| {
+                let counts = *self.streams.get(&s.id?)?;
+                Some(LiveStream { stream: s, counts })
+            })
+            .collect())
+    }
+
+    fn snapshot(&self) -> Result<Vec<LiveStream>, ApiError> {
+        let ids: Vec<i64> = self.streams.keys().copied().collect();
+        self.live_streams(&ids)
+    }
+
+    /// Coalesce every change since the last flush into one message per stream
+    fn flush(&mut self) {
+        if self.dirty.is_empty() && self.updated.is_empty() && self.afk_changed.is_empty() {
+            return;
+        }
+
+        let afk_changed: HashSet<i64> =
+            self.afk_changed.drain().chain(self.dirty.clone()).collect();
+        for id in afk_changed {
+            if let Err(e) = self.sync_afk(id) {
+                log::error!("failed to sync afk for stream {}: {}", id, e);
+            }
+        }
+
+        let mut messages = Vec::new();
+        let mut load = Vec::new();
+
+        for id in self.dirty.drain().collect::<Vec<_>>() {
+            match self.streams.get(&id) {
+                None => {
+                    if self.announced.remove(&id) {
+                        messages.push(ServerEvent::StreamRemoved(id).to_text());
+                    }
+                    self.updated.remove(&id);
+                }
+                Some(counts) => {
+                    if self.announced.contains(&id) && !self.updated.contains(&id) {
+                        messages.push(ServerEvent::RustlersSet(id, counts.rustlers).to_text());
+                    } else {
+                        load.push(id);
+                    }
+                    self.updated.remove(&id);
+                }
+            }
+        }
+        let streams = &self.streams;
+        load.extend(self.updated.drain().filter(|id| streams.contains_key(id)));
+
+        if !load.is_empty() {
+            match self.live_streams(&load) {
+                Ok(live) => {
+                    let visible: HashSet<i64> = live.iter().filter_map(|l| l.stream.id).collect();
+                    for id in load.iter().filter(|id| !visible.contains(id)) {
+                        if self.announced.remove(id) {
+                            messages.push(ServerEvent::StreamRemoved(*id).to_text());
+                        }
+                    }
+                    for l in live {
+                        self.announced.extend(l.stream.id);
+                        messages.push(ServerEvent::StreamUpdated(l).to_text());
+                    }
+                }
+                Err(e) => log::error!("failed to load streams for broadcast: {}", e),
+            }
+        }
+
+        for msg in messages {
+            for sub in self.subscribers.values() {
+                let _ = sub.broadcast.do_send(Broadcast(msg.clone()));
+            }
+        }
+    }
+}
+
+impl Actor for StreamRegistry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(BROADCAST_INTERVAL, |act, _| act.flush());
+    }
+}
+
+/// A preformatted text frame pushed to a subscribed websocket session
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Broadcast(pub String);
+
+/// Subscribe a websocket session to stream list changes
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Connect {
+    pub id: Uuid,
+    pub addr: Recipient<Broadcast>,
+    pub kick: Recipient<Kicked>,
+    pub moved: Recipient<Moved>,
+    pub terminate: Recipient<Terminated>,
+    pub user_id: Option<String>,
+}
+
+/// Unsubscribe a websocket session and remove it from its stream
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub id: Uuid,
+}
+
+/// Register a session as watching a stream, replacing any previous stream
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Join {
+    pub id: Uuid,
+    pub stream_id: i64,
+    pub afk: bool,
+}
+
+/// Unregister a session from whatever stream it is watching
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Leave {
+    pub id: Uuid,
+}
+
+/// Set whether a session is afk on the stream it is watching
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetAfk {
+    pub id: Uuid,
+    pub afk: bool,
+}
+
+/// Notify subscribers that a stream's stored metadata changed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StreamUpdated {
+    pub stream_id: i64,
+}
+
+/// Move every session watching a stream to the stream's new id
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StreamRekeyed {
+    pub old_id: i64,
+    pub stream: stream::Stream,
+}
+
+/// Sent to a session whose stream now lives under a new id
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Moved {
+    pub old_id: i64,
+    pub stream: stream::Stream,
+}
+
+/// Remove every session watching one of the streams, telling each why
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct BanStreams {
+    pub stream_ids: Vec<i64>,
+    pub reason: String,
+}
+
+/// Sent to a session removed from the stream it was watching
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kicked {
+    pub stream_id: i64,
+    pub reason: String,
+}
+
+/// Close every websocket session of a user, replying with how many were closed
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct TerminateUser {
+    pub user_id: String,
+    pub reason: String,
+}
+
+/// Sent to a session that must close its connection
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Terminated {
+    pub reason: String,
+}
+
+/// Get the counts for a single stream
+#[derive(Message)]
+#[rtype(result = "Counts")]
+pub struct GetCounts {
+    pub stream_id: i64,
+}
+
+/// Get the counts for every stream with at least one rustler
+#[derive(Message)]
+#[rtype(result = "HashMap<i64, Counts>")]
+pub struct GetAllCounts;
+
+impl Handler<Connect> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, _: &mut Context<Self>) {
+        match self.snapshot() {
+            Ok(streams) => {
+                let _ = msg
+                    .addr
+                    .do_send(Broadcast(ServerEvent::StreamsSet(streams).to_text()));
+            }
+            Err(e) => log::error!("failed to load stream snapshot: {}", e),
+        }
+        self.subscribers.insert(
+            msg.id,
+            Subscriber {
+                broadcast: msg.addr,
+                kick: msg.kick,
+                moved: msg.moved,
+                terminate: msg.terminate,
+                user_id: msg.user_id,
+            },
+        );
+    }
+}
+
+impl Handler<Disconnect> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
+        self.subscribers.remove(&msg.id);
+        self.remove_session(&msg.id);
+    }
+}
+
+impl Handler<Join> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
+        self.remove_session(&msg.id);
+
+        let counts = self.streams.entry(msg.stream_id).or_default();
+        counts.rustlers += 1;
+        if msg.afk {
+            counts.afk += 1;
+        }
+
+        self.sessions.insert(
+            msg.id,
+            Session {
+                stream_id: msg.stream_id,
+                afk: msg.afk,
+            },
+        );
+        self.dirty.insert(msg.stream_id);
+    }
+}
+
+impl Handler<Leave> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        self.remove_session(&msg.id);
+    }
+}
+
+impl Handler<SetAfk> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetAfk, _: &mut Context<Self>) {
+        let session = match self.sessions.get_mut(&msg.id) {
+            Some(session) if session.afk != msg.afk => session,
+            _ => return,
+        };
+        session.afk = msg.afk;
+
+        if let Some(counts) = self.streams.get_mut(&session.stream_id) {
+            if msg.afk {
+                counts.afk += 1;
+            } else {
+                counts.afk -= 1;
+            }
+        }
+        self.afk_changed.insert(session.stream_id);
+    }
+}
+
+impl Handler<StreamUpdated> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: StreamUpdated, _: &mut Context<Self>) {
+        self.updated.insert(msg.stream_id);
+    }
+}
+
+impl Handler<StreamRekeyed> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: StreamRekeyed, _: &mut Context<Self>) {
+        let new_id = match msg.stream.id {
+            Some(id) if id != msg.old_id => id,
+            _ => return,
+        };
+
+        if let Some(counts) = self.streams.remove(&msg.old_id) {
+            self.streams.insert(new_id, counts);
+        }
+        if let Some(afk) = self.afk_flags.remove(&msg.old_id) {
+            self.afk_flags.insert(new_id, afk);
+        }
+        for (id, session) in self.sessions.iter_mut() {
+            if session.stream_id != msg.old_id {
+                continue;
+            }
+            session.stream_id = new_id;
+            if let Some(sub) = self.subscribers.get(id) {
+                let _ = sub.moved.do_send(Moved {
+                    old_id: msg.old_id,
+                    stream: msg.stream.clone(),
+                });
+            }
+        }
+
+        self.dirty.insert(msg.old_id);
+        self.dirty.insert(new_id);
+        self.updated.insert(new_id);
+    }
+}
+
+impl Handler<BanStreams> for StreamRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: BanStreams, _: &mut Context<Self>) {
+        let kicked: Vec<(Uuid, i64)> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| msg.stream_ids.contains(&s.stream_id))
+            .map(|(id, s)| (*id, s.stream_id))
+            .collect();
+
+        for (id, stream_id) in kicked {
+            if let Some(sub) = self.subscribers.get(&id) {
+                let _ = sub.kick.do_send(Kicked {
+                    stream_id,
+                    reason: msg.reason.clone(),
+                });
+            }
+            self.remove_session(&id);
+        }
+    }
+}
+
+impl Handler<TerminateUser> for StreamRegistry {
+    type Result = usize;
+
+    fn handle(&mut self, msg: TerminateUser, _: &mut Context<Self>) -> usize {
+        let terminated: Vec<Uuid> = self
+            .subscribers
+            .iter()
+            .filter(|(_, sub)| sub.user_id.as_deref() == Some(msg.user_id.as_str()))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &terminated {
+            if let Some(sub) = self.subscribers.remove(id) {
+                let _ = sub.terminate.do_send(Terminated {
+                    reason: msg.reason.clone(),
+                });
+            }
+            self.remove_session(id);
+        }
+        terminated.len()
+    }
+}
+
+impl Handler<GetCounts> for StreamRegistry {
+    type Result = MessageResult<GetCounts>;
+
+    fn handle(&mut self, msg: GetCounts, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.streams
+                .get(&msg.stream_id)
+                .copied()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+impl Handler<GetAllCounts> for StreamRegistry {
+    type Result = MessageResult<GetAllCounts>;
+
+    fn handle(&mut self, _: GetAllCounts, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.streams.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::setup_pool;
+
+    use std::sync::{Arc, Mutex};
+
+    /// subscriber that records every frame and kick it receives
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Broadcast> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Broadcast, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(msg.0);
+        }
+    }
+
+    impl Handler<Kicked> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Kicked, _: &mut Context<Self>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("kicked: {}", msg.reason));
+        }
+    }
+
+    impl Handler<Moved> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Moved, _: &mut Context<Self>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("moved: {:?}", msg.stream.id));
+        }
+    }
+
+    impl Handler<Terminated> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Terminated, _: &mut Context<Self>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("terminated: {}", msg.reason));
+        }
+    }
+
+    fn connect(recorder: &Addr<Recorder>) -> Connect {
+        Connect {
+            id: Uuid::new_v4(),
+            addr: recorder.clone().recipient(),
+            kick: recorder.clone().recipient(),
+            moved: recorder.clone().recipient(),
+            terminate: recorder.clone().recipient(),
+            user_id: None,
+        }
+    }
+
+    fn insert_stream(pool: &DbPool) -> i64 {
+        let stream = stream::Stream {
+            service: String::from("twitch"),
+            channel: String::from("jbpratt"),
+            ..Default::default()
+        };
+        stream::insert(pool, stream).unwrap().id.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn it_counts_rustlers_per_stream() {
+        let registry = StreamRegistry::new(setup_pool()).start();
+
+        for _ in 0..3 {
+            registry.do_send(Join {
+                id: Uuid::new_v4(),
+                stream_id: 1,
+                afk: false,
+            });
+        }
+        registry.do_send(Join {
+            id: Uuid::new_v4(),
+            stream_id: 2,
+            afk: true,
+        });
+
+        let counts = registry.send(GetCounts { stream_id: 1 }).await.unwrap();
+        assert_eq!(
+            counts,
+            Counts {
+                rustlers: 3,
+                afk: 0
+            }
+        );
+
+        let counts = registry.send(GetCounts { stream_id: 2 }).await.unwrap();
+        assert_eq!(
+            counts,
+            Counts {
+                rustlers: 1,
+                afk: 1
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn it_moves_a_session_between_streams() {
+        let registry = StreamRegistry::new(setup_pool()).start();
+        let id = Uuid::new_v4();
+
+        registry.do_send(Join {
+            id,
+            stream_id: 1,
+            afk: true,
+        });
+        registry.do_send(Join {
+            id,
+            stream_id: 2,
+            afk: false,
+        });
+
+        let all = registry.send(GetAllCounts).await.unwrap();
+        assert!(!all.contains_key(&1));
+        assert_eq!(
+            all[&2],
+            Counts {
+                rustlers: 1,
+                afk: 0
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn it_removes_a_session_on_leave() {
+        let registry = StreamRegistry::new(setup_pool()).start();
+        let id = Uuid::new_v4();
+
+        registry.do_send(Join {
+            id,
+            stream_id: 1,
+            afk: false,
+        });
+        registry.do_send(Leave { id });
+        registry.do_send(Leave { id });
+
+        let counts = registry.send(GetCounts { stream_id: 1 }).await.unwrap();
+        assert_eq!(counts, Counts::default());
+    }
+
+    #[actix_rt::test]
+    async fn it_sends_a_snapshot_on_connect() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool).start();
+
+        registry.do_send(Join {
+            id: Uuid::new_v4(),
+            stream_id,
+            afk: false,
+        });
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder(frames.clone()).start();
+        registry.send(connect(&recorder)).await.unwrap();
+        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].starts_with(r#"["STREAMS_SET",[{"#));
+        assert!(frames[0].contains(r#""rustlers":1"#));
+    }
+
+    #[actix_rt::test]
+    async fn it_coalesces_changes_into_one_broadcast() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool).start();
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder(frames.clone()).start();
+        registry.do_send(connect(&recorder));
+
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            registry.do_send(Join {
+                id: *id,
+                stream_id,
+                afk: false,
+            });
+        }
+        actix_rt::time::delay_for(BROADCAST_INTERVAL + Duration::from_millis(100)).await;
+
+        for id in &ids[1..] {
+            registry.do_send(Leave { id: *id });
+        }
+        actix_rt::time::delay_for(BROADCAST_INTERVAL).await;
+
+        registry.do_send(Disconnect { id: ids[0] });
+        actix_rt::time::delay_for(BROADCAST_INTERVAL).await;
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], r#"["STREAMS_SET",[]]"#);
+        assert!(frames[1].starts_with(r#"["STREAM_UPDATED",{"#));
+        assert!(frames[1].contains(r#""rustlers":5"#));
+        assert_eq!(frames[2], format!(r#"["RUSTLERS_SET",{},1]"#, stream_id));
+        assert_eq!(frames[3], format!(r#"["STREAM_REMOVED",{}]"#, stream_id));
+    }
+
+    #[test]
+    fn it_serializes_a_live_stream_without_duplicate_keys() {
+        let live = LiveStream {
+            stream: stream::Stream {
+                id: Some(1),
+                afk: Some(true),
+                ..Default::default()
+            },
+            counts: Counts {
+                rustlers: 3,
+                afk: 2,
+            },
+        };
+        let json = serde_json::to_string(&live).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        // a flattened key repeated in the output would collapse when parsed
+        assert_eq!(
+            value.as_object().unwrap().len(),
+            json.matches("\":").count(),
+            "{}",
+            json
+        );
+        assert_eq!(value["afk"], true);
+        assert_eq!(value["rustlers"], 3);
+        assert_eq!(value["afk_rustlers"], 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_afk_for_a_session() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let id = Uuid::new_v4();
+
+        registry.do_send(Join {
+            id,
+            stream_id,
+            afk: false,
+        });
+        registry.do_send(SetAfk { id, afk: true });
+        registry.do_send(SetAfk { id, afk: true });
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(
+            counts,
+            Counts {
+                rustlers: 1,
+                afk: 1
+            }
+        );
+
+        // every rustler is afk, so the stream is flagged afk on the next flush
+        actix_rt::time::delay_for(BROADCAST_INTERVAL + Duration::from_millis(100)).await;
+        let stream = stream::get_by_id(&pool, stream_id).unwrap();
+        assert_eq!(stream.afk, Some(true));
+
+        registry.do_send(SetAfk { id, afk: false });
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.afk, 0);
+
+        actix_rt::time::delay_for(BROADCAST_INTERVAL).await;
+        let stream = stream::get_by_id(&pool, stream_id).unwrap();
+        assert_eq!(stream.afk, Some(false));
+    }
+
+    #[actix_rt::test]
+    async fn it_kicks_sessions_off_banned_streams() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool).start();
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder(frames.clone()).start();
+        let session = connect(&recorder);
+        let id = session.id;
+        registry.do_send(session);
+        registry.do_send(Join {
+            id,
+            stream_id,
+            afk: false,
+        });
+        registry.do_send(Join {
+            id: Uuid::new_v4(),
+            stream_id: stream_id + 1,
+            afk: false,
+        });
+
+        registry.do_send(BanStreams {
+            stream_ids: vec![stream_id],
+            reason: String::from("dmca"),
+        });
+        let all = registry.send(GetAllCounts).await.unwrap();
+        assert!(!all.contains_key(&stream_id));
+        assert!(all.contains_key(&(stream_id + 1)));
+
+        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        assert!(frames
+            .lock()
+            .unwrap()
+            .contains(&String::from("kicked: dmca")));
+    }
+
+    #[actix_rt::test]
+    async fn it_removes_and_restores_a_hidden_stream() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool.clone()).start();
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder(frames.clone()).start();
+        registry.do_send(connect(&recorder));
+        registry.do_send(Join {
+            id: Uuid::new_v4(),
+            stream_id,
+            afk: false,
+        });
+        actix_rt::time::delay_for(BROADCAST_INTERVAL + Duration::from_millis(100)).await;
+
+        for hidden in &[true, false] {
+            let changes = stream::StreamModeration {
+                hidden: Some(*hidden),
+                ..Default::default()
+            };
+            stream::moderate(&pool, stream_id, &changes).unwrap();
+            registry.do_send(StreamUpdated { stream_id });
+            actix_rt::time::delay_for(BROADCAST_INTERVAL).await;
+        }
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames[1].starts_with(r#"["STREAM_UPDATED",{"#));
+        assert_eq!(frames[2], format!(r#"["STREAM_REMOVED",{}]"#, stream_id));
+        assert!(frames[3].starts_with(r#"["STREAM_UPDATED",{"#));
+    }
+
+    #[actix_rt::test]
+    async fn it_terminates_every_session_of_a_user() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool).start();
+
+        let banned = Arc::new(Mutex::new(Vec::new()));
+        let bystander = Arc::new(Mutex::new(Vec::new()));
+        let mut sessions = Vec::new();
+        for frames in &[&banned, &banned, &bystander] {
+            let recorder = Recorder((*frames).clone()).start();
+            let mut msg = connect(&recorder);
+            if Arc::ptr_eq(frames, &banned) {
+                msg.user_id = Some(String::from("banned-user"));
+            }
+            sessions.push(msg.id);
+            registry.do_send(msg);
+        }
+        for id in &sessions {
+            registry.do_send(Join {
+                id: *id,
+                stream_id,
+                afk: false,
+            });
+        }
+
+        let terminated = registry
+            .send(TerminateUser {
+                user_id: String::from("banned-user"),
+                reason: String::from("spam"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(terminated, 2);
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.rustlers, 1);
+
+        actix_rt::time::delay_for(Duration::from_millis(100)).await;
+        let banned = banned.lock().unwrap();
+        assert_eq!(
+            banned.iter().filter(|f| *f == "terminated: spam").count(),
+            2
+        );
+        assert!(!bystander
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|f| f.starts_with("terminated")));
+    }
+
+    #[actix_rt::test]
+    async fn it_moves_sessions_to_a_rekeyed_stream() {
+        let pool = setup_pool();
+        let stream_id = insert_stream(&pool);
+        let registry = StreamRegistry::new(pool.clone()).start();
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder(frames.clone()).start();
+        let conn = connect(&recorder);
+        let session_id = conn.id;
+        registry.do_send(conn);
+        registry.do_send(Join {
+            id: session_id,
+            stream_id,
+            afk: true,
+        });
+        actix_rt::time::delay_for(BROADCAST_INTERVAL + Duration::from_millis(100)).await;
+
+        let changes = stream::StreamModeration {
+            path: Some(Some(String::from("jbpratt_irl"))),
+            ..Default::default()
+        };
+        let moved = stream::moderate(&pool, stream_id, &changes).unwrap();
+        let new_id = moved.id.unwrap();
+        assert_ne!(new_id, stream_id);
+        registry.do_send(StreamRekeyed {
+            old_id: stream_id,
+            stream: moved,
+        });
+
+        let counts = registry
+            .send(GetCounts { stream_id: new_id })
+            .await
+            .unwrap();
+        assert_eq!(
+            counts,
+            Counts {
+                rustlers: 1,
+                afk: 1
+            }
+        );
+        let all = registry.send(GetAllCounts).await.unwrap();
+        assert!(!all.contains_key(&stream_id));
+
+        actix_rt::time::delay_for(BROADCAST_INTERVAL).await;
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 5, "{:?}", frames);
+        assert_eq!(frames[2], format!("moved: Some({})", new_id));
+        assert!(frames[3..].contains(&format!(r#"["STREAM_REMOVED",{}]"#, stream_id)));
+        assert!(frames[3..].iter().any(
+            |f| f.starts_with(r#"["STREAM_UPDATED",{"id":"#) && f.contains(&new_id.to_string())
+        ));
+    }
+}
@@ -1,43 +1,291 @@
-use actix_identity::Identity;
-use actix_web::{http, web, HttpRequest, HttpResponse};
+use actix_web::http::Cookie;
+use actix_web::{http, web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::Utc;
 use url::Url;
+use uuid::Uuid;
 
+use crate::admin;
+use crate::channel::Channel;
 use crate::config::CONFIG;
 use crate::database::DbPool;
 use crate::errors::ApiError;
-// streams
-// login
-// oauth
-// admin/profiles/*/username
-// admin/streams/**
+use crate::middleware::auth::{session_cookie, start_session, AuthenticatedUser, SESSION_COOKIE};
+use crate::middleware::ip_ban::client_ip;
+use crate::models::{session, user};
+use crate::profile;
+use crate::services::twitch;
+use crate::state::AppState;
+use crate::streams;
+
+const AUTHORIZE_URL: &str = "https://id.twitch.tv/oauth2/authorize";
+/// Cookie holding the `state` sent to twitch, checked when the user returns
+const STATE_COOKIE: &str = "oauth_state";
+/// How long a user has to finish logging in with twitch
+const STATE_TTL_SECS: i64 = 600;
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api")
             .route("/login", web::get().to(login))
-            .route("/oauth", web::get().to(oauth)),
+            .route("/oauth", web::get().to(oauth))
+            .route("/logout", web::post().to(logout))
+            .service(web::scope("/streams").configure(streams::routes))
+            .service(web::scope("/profile").configure(profile::routes))
+            .service(web::scope("/admin").configure(admin::routes)),
     );
 }
 
-pub async fn oauth(req: HttpRequest) -> HttpResponse {
-    dbg!(req);
-    todo!()
+#[derive(Deserialize)]
+pub struct OAuthQuery {
+    code: String,
+    state: String,
+}
+
+pub async fn oauth(
+    req: HttpRequest,
+    query: web::Query<OAuthQuery>,
+    pool: web::Data<DbPool>,
+    state: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let expected = req.cookie(STATE_COOKIE);
+    if expected.as_ref().map(|c| c.value()) != Some(query.state.as_str()) {
+        return Err(ApiError::BadRequest(String::from("invalid oauth state")).into());
+    }
+
+    let twitch_user = get_twitch_user(&state, &query.code)
+        .await
+        .map_err(ApiError::from)?;
+
+    let ip = match client_ip(&req) {
+        Some(ip) => ip.to_string(),
+        None => req.connection_info().remote().unwrap_or("").to_owned(),
+    };
+    let session = login_user(&pool, &twitch_user, &ip).and_then(|u| start_session(&pool, &u.id))?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, "/")
+        .cookie(session_cookie(session))
+        .del_cookie(&Cookie::named(STATE_COOKIE))
+        .finish())
+}
+
+/// Revoke the session of the request and drop its cookie
+pub async fn logout(
+    auth: AuthenticatedUser,
+    pool: web::Data<DbPool>,
+) -> actix_web::Result<HttpResponse> {
+    session::revoke(&pool, &auth.session_id)?;
+
+    Ok(HttpResponse::NoContent()
+        .del_cookie(&Cookie::build(SESSION_COOKIE, "").path("/").finish())
+        .finish())
 }
 
-pub async fn login(req: HttpRequest) -> HttpResponse {
-    let url = "https://api.twitch.tv/kraken/oauth2/authorize?response_type=code&scope=user_read";
+pub async fn login(_req: HttpRequest) -> HttpResponse {
+    let state = Uuid::new_v4().to_simple().to_string();
     let url = Url::parse_with_params(
-        &url,
+        AUTHORIZE_URL,
         &[
-            ("client_id", CONFIG.twitch_client_id.clone()),
-            ("redirect_uri", CONFIG.twitch_redirect_url.clone()),
+            ("response_type", "code"),
+            ("client_id", CONFIG.twitch_client_id.as_str()),
+            ("redirect_uri", CONFIG.twitch_redirect_url.as_str()),
+            ("state", state.as_str()),
         ],
     )
     .expect("failed to parse twitch login url")
     .to_string();
 
+    let state_cookie = Cookie::build(STATE_COOKIE, state)
+        .path("/api")
+        .http_only(true)
+        .max_age(STATE_TTL_SECS)
+        .finish();
+
     HttpResponse::Found()
         .header(http::header::LOCATION, url)
+        .cookie(state_cookie)
         .finish()
-        .into_body()
+}
+
+async fn get_twitch_user(state: &AppState, code: &str) -> anyhow::Result<twitch::User> {
+    let token = state
+        .twitch_tokens
+        .exchange_code(code, &CONFIG.twitch_redirect_url)
+        .await?;
+    twitch::get_token_user(&state.client, &state.twitch_tokens, &token).await
+}
+
+/// Find or create the user for a twitch account, recording where they logged in from
+fn login_user(pool: &DbPool, twitch_user: &twitch::User, ip: &str) -> Result<user::User, ApiError> {
+    // twitch documents ids as numeric strings, anything else is their fault
+    let twitch_id = twitch_user.id.parse::<i64>().map_err(|_| {
+        ApiError::SchemaValidation(format!("invalid twitch id: {}", twitch_user.id))
+    })?;
+
+    match user::get_by_twitch_id(pool, twitch_id) {
+        Ok(mut u) => {
+            let now = Utc::now().naive_utc();
+            u.last_ip = ip.to_string();
+            u.last_seen = now;
+            u.updated_at = now;
+            user::update(pool, &u)?;
+            Ok(u)
+        }
+        Err(ApiError::NotFound(_)) => {
+            let chn = Channel::new(
+                twitch_user.login.clone(),
+                String::from("twitch"),
+                String::new(),
+            )?;
+            user::create(pool, twitch_id, chn, &twitch_user.display_name, ip)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::models::user::Role;
+    use actix_web::{test, App};
+
+    use std::sync::Arc;
+
+    fn twitch_user() -> twitch::User {
+        twitch::User {
+            id: String::from("141981764"),
+            login: String::from("jbpratt"),
+            display_name: String::from("JBPratt"),
+            offline_image_url: String::new(),
+        }
+    }
+
+    #[test]
+    fn it_creates_then_updates_a_user_on_login() {
+        let pool = setup_pool();
+
+        let created = login_user(&pool, &twitch_user(), "10.0.0.1").unwrap();
+        assert_eq!(created.twitch_id, 141981764);
+        assert_eq!(created.name, "JBPratt");
+        assert_eq!(created.stream_path, "/twitch/jbpratt");
+
+        let updated = login_user(&pool, &twitch_user(), "10.0.0.2").unwrap();
+        assert_eq!(updated.id, created.id);
+
+        let found = user::get_by_twitch_id(&pool, 141981764).unwrap();
+        assert_eq!(found.last_ip, "10.0.0.2");
+        assert!(found.last_seen >= created.last_seen);
+    }
+
+    #[test]
+    fn it_treats_a_non_numeric_twitch_id_as_an_upstream_error() {
+        use actix_web::ResponseError;
+
+        let mut user = twitch_user();
+        user.id = String::from("not-a-number");
+
+        let err = login_user(&setup_pool(), &user, "10.0.0.1").unwrap_err();
+        assert!(matches!(err, ApiError::SchemaValidation(_)));
+        assert_eq!(err.status_code(), http::StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_rt::test]
+    async fn it_redirects_to_twitch_with_a_state() {
+        let mut app = test::init_service(App::new().configure(routes)).await;
+        let req = test::TestRequest::get().uri("/api/login").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::FOUND);
+
+        let location = resp.headers().get(http::header::LOCATION).unwrap();
+        let location = Url::parse(location.to_str().unwrap()).unwrap();
+        assert!(location.as_str().starts_with(AUTHORIZE_URL));
+
+        let state = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == STATE_COOKIE)
+            .unwrap();
+        assert!(location
+            .query_pairs()
+            .any(|(k, v)| k == "state" && v == state.value()));
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_a_mismatched_oauth_state() {
+        let mut app = test::init_service(
+            App::new()
+                .data(setup_pool())
+                .data(AppState::new(Arc::new(reqwest::Client::new())))
+                .configure(routes),
+        )
+        .await;
+
+        for cookie in &[None, Some("expected")] {
+            let mut req = test::TestRequest::get().uri("/api/oauth?code=abc&state=forged");
+            if let Some(value) = cookie {
+                req = req.cookie(Cookie::new(STATE_COOKIE, *value));
+            }
+            let resp = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_reports_a_failed_code_exchange_as_an_upstream_error() {
+        use crate::services::twitch_auth::TokenManager;
+
+        let twitch = test::start(|| {
+            App::new().route("/oauth2/token", web::post().to(HttpResponse::Unauthorized))
+        });
+        let client = Arc::new(reqwest::Client::new());
+        let tokens = TokenManager::new(
+            client.clone(),
+            &twitch.url("/oauth2/token"),
+            "client-id",
+            "client-secret",
+        );
+        let mut app = test::init_service(
+            App::new()
+                .data(setup_pool())
+                .data(AppState::with_twitch_tokens(client, tokens))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/oauth?code=abc&state=expected")
+            .cookie(Cookie::new(STATE_COOKIE, "expected"))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_rt::test]
+    async fn it_logs_out_and_revokes_the_session() {
+        let pool = setup_pool();
+        let (_, token) = setup_session(&pool, "rustler", Role::User);
+        let mut app = test::init_service(App::new().data(pool).configure(routes)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/logout")
+            .cookie(session_cookie(token.clone()))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NO_CONTENT);
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == SESSION_COOKIE)
+            .unwrap();
+        assert_eq!(cookie.value(), "");
+
+        // the token is no longer accepted, even when kept around
+        let req = test::TestRequest::get()
+            .uri("/api/profile")
+            .cookie(session_cookie(token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+    }
 }
@@ -15,6 +15,18 @@ table! {
         reason -> Nullable<Text>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        banned_by -> Nullable<Text>,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Text,
+        user_id -> Text,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -33,6 +45,7 @@ table! {
         viewers -> Nullable<Integer>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        nsfw -> Bool,
     }
 }
 
@@ -51,8 +64,10 @@ table! {
         ban_reason -> Nullable<Text>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
-        is_admin -> Nullable<Bool>,
+        role -> Text,
     }
 }
 
-allow_tables_to_appear_in_same_query!(banned_ip_ranges, banned_streams, streams, users,);
+joinable!(sessions -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(banned_ip_ranges, banned_streams, sessions, streams, users,);
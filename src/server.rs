@@ -1,14 +1,17 @@
 use crate::{
     config::CONFIG,
     database::DbPool,
+    errors::ApiError,
+    middleware::{auth::SessionRefresh, ip_ban::IpBan},
+    models::banned_ip_range::IpBanList,
+    poller::StreamPoller,
+    registry::StreamRegistry,
     routes::routes,
-    service,
-    service::Service,
-    services::{mixer, smashcast, twitch, youtube},
     state,
     wsservice::ws_index,
 };
 
+use actix::Actor;
 use actix_web::{get, middleware, web, App, HttpResponse, HttpServer};
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
@@ -27,19 +30,22 @@ pub async fn server() -> std::io::Result<()> {
         .build(manager)
         .expect("Failed to create pool.");
 
-    let client = Arc::new(Client::new());
-    let data = state::AppState {
-        twitch: twitch::Client::new(client.clone()),
-        mixer: mixer::Client::new(client.clone()),
-        smashcast: smashcast::Client::new(client.clone()),
-        youtube: youtube::Client::new(client.clone()),
-    };
+    let bans = IpBanList::load(pool.clone()).expect("Failed to load banned ip ranges.");
+
+    let data = state::AppState::new(Arc::new(Client::new()));
+
+    let registry = StreamRegistry::new(pool.clone()).start();
+    StreamPoller::new(pool.clone(), registry.clone(), data.clone()).start();
 
     HttpServer::new(move || {
         App::new()
+            .wrap(SessionRefresh)
+            .wrap(IpBan::new(bans.clone(), CONFIG.trusted_proxies.clone()))
             .wrap(middleware::Logger::default())
             .data(data.clone())
             .data(pool.clone())
+            .data(registry.clone())
+            .data(bans.clone())
             .service(index)
             .service(web::resource("/ws").route(web::get().to(ws_index)))
             .configure(routes)
@@ -54,32 +60,13 @@ async fn index(
     info: web::Path<(String, String)>,
     data: web::Data<state::AppState>,
 ) -> actix_web::Result<HttpResponse> {
-    match info.0.as_str() {
-        "twitch" => Ok(HttpResponse::NotFound().finish()),
-        "mixer" => {
-            let res = data
-                .mixer
-                .get_channel_by_name(info.1.as_str())
-                .await
-                .unwrap();
-            Ok(HttpResponse::Ok().json(&res as &dyn service::ServiceChannel))
-        }
-        "smashcast" => {
-            let res = data
-                .smashcast
-                .get_channel_by_name(info.1.as_str())
-                .await
-                .unwrap();
-            Ok(HttpResponse::Ok().json(&res as &dyn service::ServiceChannel))
-        }
-        "youtube" => {
-            let res = data
-                .youtube
-                .get_channel_by_name(info.1.as_str())
-                .await
-                .unwrap();
-            Ok(HttpResponse::Ok().json(&res as &dyn service::ServiceChannel))
-        }
-        _ => Ok(HttpResponse::NotFound().finish()),
-    }
+    let (service, name) = (info.0.as_str(), info.1.as_str());
+    let channel = data
+        .services
+        .get_channel(service, name)
+        .await
+        .ok_or_else(|| ApiError::NotFound(format!("no client for service: {}", service)))?
+        .map_err(ApiError::from)?;
+
+    Ok(HttpResponse::Ok().json(&*channel))
 }
@@ -2,18 +2,92 @@ use async_trait::async_trait;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use serde_json_schema::Schema;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::sync::Arc;
 
 use crate::errors::ApiError;
+use crate::services::twitch_auth::TokenManager;
+use crate::services::SERVICES;
+
+/// Shared resources service clients are built from
+#[derive(Clone)]
+pub struct ServiceContext {
+    pub client: Arc<reqwest::Client>,
+    pub twitch_tokens: TokenManager,
+}
 
 #[async_trait]
-pub trait Service<J> {
-    fn new(client: Arc<reqwest::Client>) -> Self;
+pub trait Service {
+    type Channel: ServiceChannel;
+
+    fn new(ctx: &ServiceContext) -> Self;
     fn get_schema() -> &'static str;
-    async fn get_channel_by_name(&self, name: &str) -> anyhow::Result<J>;
+    async fn get_channel_by_name(&self, name: &str) -> anyhow::Result<Self::Channel>;
+}
+
+/// Object safe view of a `Service`, so clients can be stored by service name
+#[async_trait]
+pub trait DynService: Send + Sync {
+    async fn get_channel(&self, name: &str) -> anyhow::Result<Box<dyn ServiceChannel>>;
 }
 
+#[async_trait]
+impl<T> DynService for T
+where
+    T: Service + Send + Sync,
+    T::Channel: 'static,
+{
+    async fn get_channel(&self, name: &str) -> anyhow::Result<Box<dyn ServiceChannel>> {
+        let channel = self.get_channel_by_name(name).await?;
+        Ok(Box::new(channel))
+    }
+}
+
+/// Constructs the API client for a service
+pub type ServiceFactory = fn(&ServiceContext) -> Box<dyn DynService>;
+
+/// Build a boxed client for any `Service`, for use as a `ServiceFactory`
+pub fn boxed<T>(ctx: &ServiceContext) -> Box<dyn DynService>
+where
+    T: Service + Send + Sync + 'static,
+    T::Channel: 'static,
+{
+    Box::new(T::new(ctx))
+}
+
+/// API clients keyed by service name
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    services: HashMap<&'static str, Arc<dyn DynService>>,
+}
+
+impl ServiceRegistry {
+    /// Build a client for every registered service that has one
+    pub fn new(ctx: &ServiceContext) -> Self {
+        let services = SERVICES
+            .iter()
+            .filter_map(|(name, factory)| factory.map(|f| (*name, Arc::from(f(ctx)))))
+            .collect();
+        Self { services }
+    }
+
+    pub fn get(&self, service: &str) -> Option<&Arc<dyn DynService>> {
+        self.services.get(service)
+    }
+
+    /// Look up a channel with the matching service client, if the service has one
+    pub async fn get_channel(
+        &self,
+        service: &str,
+        name: &str,
+    ) -> Option<anyhow::Result<Box<dyn ServiceChannel>>> {
+        let client = self.get(service)?.clone();
+        Some(client.get_channel(name).await)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[async_trait]
 pub trait API {
     async fn request<'a>(
@@ -22,12 +96,13 @@ pub trait API {
     ) -> anyhow::Result<reqwest::Response, reqwest::Error>;
 }
 
-pub trait ServiceChannel {
+pub trait ServiceChannel: Send + Sync {
     fn get_live(&self) -> bool;
     fn is_nsfw(&self) -> bool;
     fn get_title(&self) -> String;
     fn get_thumbnail(&self) -> String;
-    fn get_viewers(&self) -> u32;
+    /// `None` when the service reports a count that isn't a valid number
+    fn get_viewers(&self) -> Option<u32>;
 }
 
 impl Serialize for dyn ServiceChannel {
@@ -54,3 +129,31 @@ pub fn validate_schema(
         .validate(data)
         .map_err(|ss| ApiError::SchemaValidation(ss.into_iter().collect()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::valid_service;
+
+    #[test]
+    fn it_registers_clients_by_service_name() {
+        let client = Arc::new(reqwest::Client::new());
+        let registry = ServiceRegistry::new(&ServiceContext {
+            twitch_tokens: TokenManager::from_config(client.clone()),
+            client,
+        });
+        assert!(registry.get("youtube").is_some());
+        assert!(registry.get("smashcast").is_some());
+        assert!(registry.get("angelthump").is_none());
+        assert!(registry.get("chaturbate").is_none());
+    }
+
+    #[test]
+    fn it_validates_services_from_the_registry() {
+        for (name, _) in SERVICES {
+            assert!(valid_service(name));
+        }
+        assert!(!valid_service("chaturbate"));
+        assert!(!valid_service("mixer"));
+    }
+}
@@ -1,4 +1,22 @@
-pub mod mixer;
+use crate::service::{boxed, ServiceFactory};
+
 pub mod smashcast;
 pub mod twitch;
+pub mod twitch_auth;
 pub mod youtube;
+
+/// Every supported service, with the factory for its API client if it has one.
+/// Adding a provider only requires an entry here.
+pub const SERVICES: &[(&str, Option<ServiceFactory>)] = &[
+    ("advanced", None),
+    ("angelthump", None),
+    ("facebook", None),
+    ("m3u8", None),
+    ("smashcast", Some(boxed::<smashcast::Client>)),
+    ("twitch", Some(boxed::<twitch::Client>)),
+    ("twitch-vod", None),
+    ("ustream", None),
+    ("vaughn", None),
+    ("youtube", Some(boxed::<youtube::Client>)),
+    ("youtube-playlist", None),
+];
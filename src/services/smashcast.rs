@@ -6,7 +6,7 @@ use serde_json::Value;
 
 use std::sync::Arc;
 
-use crate::service::{validate_schema, Service, ServiceChannel, API};
+use crate::service::{validate_schema, Service, ServiceChannel, ServiceContext, API};
 
 const URL: &str = "https://api.smashcast.tv/media/live/";
 
@@ -48,9 +48,13 @@ impl API for Client {
 }
 
 #[async_trait]
-impl Service<Channel> for Client {
-    fn new(client: Arc<reqwest::Client>) -> Client {
-        Client { client }
+impl Service for Client {
+    type Channel = Channel;
+
+    fn new(ctx: &ServiceContext) -> Client {
+        Client {
+            client: ctx.client.clone(),
+        }
     }
 
     fn get_schema() -> &'static str {
@@ -100,11 +104,7 @@ impl Service<Channel> for Client {
                 let results: VideosResult = serde_json::from_value(json_resp)?;
                 Ok(results.livestream[0].clone())
             }
-            Err(e) => Err(anyhow!(
-                "response failed validation: {} {}",
-                json_resp.to_string(),
-                e
-            )),
+            Err(e) => Err(anyhow!("response failed validation: {} {}", json_resp, e)),
         }
     }
 }
@@ -122,7 +122,7 @@ impl ServiceChannel for Channel {
     fn get_thumbnail(&self) -> String {
         format!("https://edge.sf.hitbox.tv{}", self.media_thumbnail)
     }
-    fn get_viewers(&self) -> u32 {
-        self.media_views.parse::<u32>().unwrap()
+    fn get_viewers(&self) -> Option<u32> {
+        self.media_views.parse::<u32>().ok()
     }
 }
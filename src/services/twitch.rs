@@ -1,35 +1,97 @@
-use anyhow::Context;
+use anyhow::anyhow;
 use async_trait::async_trait;
-use reqwest::header::{HeaderMap, AUTHORIZATION};
-use reqwest::{header, Response};
+use reqwest::{Response, StatusCode};
 use serde::Deserialize;
+use serde_json::Value;
+
 use std::sync::Arc;
 
-use crate::config::CONFIG;
-use crate::service::{Service, ServiceChannel, API};
+use crate::service::{validate_schema, Service, ServiceChannel, ServiceContext, API};
+use crate::services::twitch_auth::TokenManager;
 
 const URL: &str = "https://api.twitch.tv/helix/";
+const THUMBNAIL_WIDTH: u32 = 640;
+const THUMBNAIL_HEIGHT: u32 = 360;
+
+const USERS_SCHEMA: &str = r#"
+  {
+    "type": "object",
+    "properties": {
+      "data": {
+        "type": "array",
+        "items": {
+          "type": "object",
+          "properties": {
+            "id": {"type": "string"},
+            "login": {"type": "string"},
+            "display_name": {"type": "string"},
+            "offline_image_url": {"type": "string"}
+          },
+          "required": ["id", "login", "display_name", "offline_image_url"]
+        }
+      }
+    },
+    "required": ["data"]
+  }"#;
 
 #[derive(Deserialize, Debug)]
-pub struct Channel {
-    game: String,
-    viewers: u32,
-    preview: String,
-    #[serde(rename(deserialize = "channel"))]
-    name: Name,
+struct Data<T> {
+    data: Vec<T>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Name {
-    display_name: String,
+#[derive(Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+    pub offline_image_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Stream {
+    #[serde(rename(deserialize = "type"))]
+    pub kind: String,
+    pub title: String,
+    pub viewer_count: u32,
+    pub thumbnail_url: String,
+    #[serde(default)]
+    pub is_mature: bool,
+}
+
+/// A Twitch user joined with their stream, which is `None` while offline
+#[derive(Debug, Clone)]
+pub struct Channel {
+    user: User,
+    stream: Option<Stream>,
 }
 
 #[derive(Clone)]
 pub struct Client {
     client: Arc<reqwest::Client>,
-    token: String,
-    client_id: String,
-    //client_secret: String,
+    tokens: TokenManager,
+    url: String,
+}
+
+impl Client {
+    async fn get_json(&self, path: &str, query: &[(&str, &str)]) -> anyhow::Result<Value> {
+        let url = self.url.clone() + path;
+        let token = self.tokens.token().await?;
+        let resp = self
+            .request(self.client.get(&url).query(query).bearer_auth(&token))
+            .await;
+
+        // the token may have been revoked before it expired, retry once with a new one
+        let resp = match resp {
+            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
+                self.tokens.invalidate(&token).await;
+                let token = self.tokens.token().await?;
+                self.request(self.client.get(&url).query(query).bearer_auth(&token))
+                    .await?
+            }
+            resp => resp?,
+        };
+        Ok(resp.json::<Value>().await?)
+    }
 }
 
 #[async_trait]
@@ -38,17 +100,7 @@ impl API for Client {
         &self,
         req: reqwest::RequestBuilder,
     ) -> anyhow::Result<Response, reqwest::Error> {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            format!("Bearer {}", self.token).parse().unwrap(),
-        );
-        headers.insert("Client-ID", self.client_id.parse().unwrap());
-        headers.insert(
-            header::ACCEPT,
-            "application/vnd.twitchtv.v5+json".parse().unwrap(),
-        );
-        let req = req.headers(headers).build()?;
+        let req = req.header("Client-ID", self.tokens.client_id()).build()?;
         let resp = self.client.execute(req).await?;
 
         resp.error_for_status_ref()?;
@@ -57,44 +109,279 @@ impl API for Client {
 }
 
 #[async_trait]
-impl Service<Channel> for Client {
-    fn new(client: Arc<reqwest::Client>) -> Client {
+impl Service for Client {
+    type Channel = Channel;
+
+    fn new(ctx: &ServiceContext) -> Client {
         Client {
-            client,
-            token: CONFIG.twitch_client_secret.clone(),
-            client_id: CONFIG.twitch_client_id.clone(),
+            client: ctx.client.clone(),
+            tokens: ctx.twitch_tokens.clone(),
+            url: URL.to_owned(),
         }
     }
 
     fn get_schema() -> &'static str {
         r#"
-            "#
+          {
+            "type": "object",
+            "properties": {
+              "data": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "type": {"type": "string"},
+                    "title": {"type": "string"},
+                    "viewer_count": {"type": "integer"},
+                    "thumbnail_url": {"type": "string"},
+                    "is_mature": {"type": "boolean"}
+                  },
+                  "required": ["type", "title", "viewer_count", "thumbnail_url"]
+                }
+              }
+            },
+            "required": ["data"]
+          }"#
     }
 
     async fn get_channel_by_name(&self, name: &str) -> anyhow::Result<Channel> {
-        let url = URL.to_owned() + name;
-        self.request(self.client.get(&url))
-            .await?
-            .json::<Channel>()
-            .await
-            .context("failed to make request")
+        let users = self.get_json("users", &[("login", name)]).await?;
+        validate_schema(&users, USERS_SCHEMA)
+            .map_err(|e| anyhow!("response failed validation: {} {}", users, e))?;
+
+        let streams = self.get_json("streams", &[("user_login", name)]).await?;
+        validate_schema(&streams, Client::get_schema())
+            .map_err(|e| anyhow!("response failed validation: {} {}", streams, e))?;
+
+        Channel::from_responses(users, streams)
+    }
+}
+
+/// The Twitch user a user access token was issued to
+pub async fn get_token_user(
+    client: &reqwest::Client,
+    tokens: &TokenManager,
+    access_token: &str,
+) -> anyhow::Result<User> {
+    let users = client
+        .get(&(URL.to_owned() + "users"))
+        .bearer_auth(access_token)
+        .header("Client-ID", tokens.client_id())
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    validate_schema(&users, USERS_SCHEMA)
+        .map_err(|e| anyhow!("response failed validation: {} {}", users, e))?;
+
+    let users: Data<User> = serde_json::from_value(users)?;
+    users
+        .data
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("twitch user not found"))
+}
+
+impl Channel {
+    fn from_responses(users: Value, streams: Value) -> anyhow::Result<Self> {
+        let users: Data<User> = serde_json::from_value(users)?;
+        let streams: Data<Stream> = serde_json::from_value(streams)?;
+
+        let user = users
+            .data
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("twitch user not found"))?;
+
+        Ok(Self {
+            user,
+            stream: streams.data.into_iter().next(),
+        })
     }
 }
 
 impl ServiceChannel for Channel {
     fn get_live(&self) -> bool {
-        true
+        matches!(&self.stream, Some(stream) if stream.kind == "live")
     }
     fn is_nsfw(&self) -> bool {
-        false
+        self.stream.as_ref().is_some_and(|s| s.is_mature)
     }
     fn get_title(&self) -> String {
-        self.game.clone()
+        match &self.stream {
+            Some(stream) => stream.title.clone(),
+            None => self.user.display_name.clone(),
+        }
     }
     fn get_thumbnail(&self) -> String {
-        self.preview.clone()
+        match &self.stream {
+            Some(stream) => stream
+                .thumbnail_url
+                .replace("{width}", &THUMBNAIL_WIDTH.to_string())
+                .replace("{height}", &THUMBNAIL_HEIGHT.to_string()),
+            None => self.user.offline_image_url.clone(),
+        }
+    }
+    fn get_viewers(&self) -> Option<u32> {
+        Some(self.stream.as_ref().map_or(0, |s| s.viewer_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use serde_json::json;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct MockTwitch {
+        issued: AtomicUsize,
+        requests: AtomicUsize,
+        /// the token accepted by the API, every other token is refused
+        accepted: Option<&'static str>,
+    }
+
+    async fn issue_token(
+        twitch: web::Data<MockTwitch>,
+        form: web::Form<Vec<(String, String)>>,
+    ) -> HttpResponse {
+        if !form
+            .iter()
+            .any(|(k, v)| k == "grant_type" && v == "client_credentials")
+        {
+            return HttpResponse::BadRequest().finish();
+        }
+        let n = twitch.issued.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Ok().json(json!({
+            "access_token": format!("token-{}", n),
+            "expires_in": 3600,
+            "token_type": "bearer",
+        }))
+    }
+
+    async fn get_users(twitch: web::Data<MockTwitch>, req: HttpRequest) -> HttpResponse {
+        twitch.requests.fetch_add(1, Ordering::SeqCst);
+        let auth = req
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok());
+        match twitch.accepted {
+            Some(token) if auth == Some(&format!("Bearer {}", token)) => {
+                HttpResponse::Ok().json(users())
+            }
+            _ => HttpResponse::Unauthorized().finish(),
+        }
     }
-    fn get_viewers(&self) -> u32 {
-        self.viewers
+
+    fn mock_twitch(accepted: Option<&'static str>) -> (test::TestServer, web::Data<MockTwitch>) {
+        let twitch = web::Data::new(MockTwitch {
+            accepted,
+            ..Default::default()
+        });
+        let data = twitch.clone();
+        let srv = test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/oauth2/token", web::post().to(issue_token))
+                .route("/helix/users", web::get().to(get_users))
+        });
+        (srv, twitch)
+    }
+
+    fn client(srv: &test::TestServer) -> Client {
+        let client = Arc::new(reqwest::Client::new());
+        Client {
+            tokens: TokenManager::new(
+                client.clone(),
+                &srv.url("/oauth2/token"),
+                "client-id",
+                "client-secret",
+            ),
+            client,
+            url: srv.url("/helix/"),
+        }
+    }
+
+    fn users() -> Value {
+        json!({
+            "data": [{
+                "id": "141981764",
+                "login": "jbpratt",
+                "display_name": "jbpratt",
+                "offline_image_url": "https://static-cdn.jtvnw.net/offline.png"
+            }]
+        })
+    }
+
+    #[test]
+    fn it_maps_a_live_channel() {
+        let streams = json!({
+            "data": [{
+                "type": "live",
+                "title": "rustling",
+                "viewer_count": 78365,
+                "thumbnail_url": "https://static-cdn.jtvnw.net/live_user_jbpratt-{width}x{height}.jpg",
+                "is_mature": true
+            }],
+            "pagination": {}
+        });
+        assert!(validate_schema(&users(), USERS_SCHEMA).is_ok());
+        assert!(validate_schema(&streams, Client::get_schema()).is_ok());
+
+        let channel = Channel::from_responses(users(), streams).unwrap();
+        assert!(channel.get_live());
+        assert!(channel.is_nsfw());
+        assert_eq!(channel.get_title(), "rustling");
+        assert_eq!(channel.get_viewers(), Some(78365));
+        assert_eq!(
+            channel.get_thumbnail(),
+            "https://static-cdn.jtvnw.net/live_user_jbpratt-640x360.jpg"
+        );
+    }
+
+    #[test]
+    fn it_maps_an_offline_channel() {
+        let streams = json!({"data": [], "pagination": {}});
+        assert!(validate_schema(&streams, Client::get_schema()).is_ok());
+
+        let channel = Channel::from_responses(users(), streams).unwrap();
+        assert!(!channel.get_live());
+        assert!(!channel.is_nsfw());
+        assert_eq!(channel.get_viewers(), Some(0));
+        assert_eq!(
+            channel.get_thumbnail(),
+            "https://static-cdn.jtvnw.net/offline.png"
+        );
+    }
+
+    #[test]
+    fn it_fails_on_an_unknown_user() {
+        let channel = Channel::from_responses(json!({"data": []}), json!({"data": []}));
+        assert!(channel.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn it_retries_with_a_new_token_when_unauthorized() {
+        let (srv, twitch) = mock_twitch(Some("token-2"));
+        let client = client(&srv);
+
+        let users = client.get_json("users", &[("login", "jbpratt")]).await;
+        assert_eq!(users.unwrap(), self::users());
+        assert_eq!(twitch.issued.load(Ordering::SeqCst), 2);
+        assert_eq!(twitch.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_gives_up_when_the_new_token_is_refused() {
+        let (srv, twitch) = mock_twitch(None);
+        let client = client(&srv);
+
+        let users = client.get_json("users", &[("login", "jbpratt")]).await;
+        assert!(users.is_err());
+        assert_eq!(twitch.issued.load(Ordering::SeqCst), 2);
+        assert_eq!(twitch.requests.load(Ordering::SeqCst), 2);
     }
 }
@@ -0,0 +1,241 @@
+use anyhow::anyhow;
+use futures::lock::Mutex;
+use serde::Deserialize;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::CONFIG;
+
+/// How long before expiry a cached token is replaced
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Clone)]
+struct AppToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+impl AppToken {
+    fn is_fresh(&self) -> bool {
+        Instant::now() + REFRESH_MARGIN < self.expires_at
+    }
+}
+
+/// Fetches Twitch app access tokens with the client credentials grant and
+/// caches them until shortly before they expire
+#[derive(Clone)]
+pub struct TokenManager {
+    client: Arc<reqwest::Client>,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    token: Arc<Mutex<Option<AppToken>>>,
+}
+
+impl TokenManager {
+    pub fn new(
+        client: Arc<reqwest::Client>,
+        token_url: &str,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Self {
+        Self {
+            client,
+            token_url: token_url.to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// A manager for the app credentials from the configuration
+    pub fn from_config(client: Arc<reqwest::Client>) -> Self {
+        Self::new(
+            client,
+            &CONFIG.twitch_token_url,
+            &CONFIG.twitch_client_id,
+            &CONFIG.twitch_client_secret,
+        )
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// A valid access token, requesting a new one if the cached token is
+    /// missing or about to expire
+    pub async fn token(&self) -> anyhow::Result<String> {
+        // held across the request so concurrent callers share one refresh
+        let mut token = self.token.lock().await;
+        if let Some(t) = token.as_ref().filter(|t| t.is_fresh()) {
+            return Ok(t.access_token.clone());
+        }
+
+        let fresh = self.request_token().await?;
+        let access_token = fresh.access_token.clone();
+        *token = Some(fresh);
+        Ok(access_token)
+    }
+
+    /// Drop a token Twitch rejected, unless it has already been replaced
+    pub async fn invalidate(&self, rejected: &str) {
+        let mut token = self.token.lock().await;
+        if matches!(token.as_ref(), Some(t) if t.access_token == rejected) {
+            *token = None;
+        }
+    }
+
+    /// Exchange an OAuth authorization code for a user access token
+    pub async fn exchange_code(&self, code: &str, redirect_uri: &str) -> anyhow::Result<String> {
+        let body = self
+            .grant(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+            ])
+            .await?;
+        Ok(body.access_token)
+    }
+
+    async fn request_token(&self) -> anyhow::Result<AppToken> {
+        let body = self.grant(&[("grant_type", "client_credentials")]).await?;
+        Ok(AppToken {
+            access_token: body.access_token,
+            expires_at: Instant::now() + Duration::from_secs(body.expires_in),
+        })
+    }
+
+    async fn grant(&self, params: &[(&str, &str)]) -> anyhow::Result<TokenResponse> {
+        let mut form = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        form.extend_from_slice(params);
+
+        let resp = self.client.post(&self.token_url).form(&form).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("twitch token request failed: {}", resp.status()));
+        }
+        Ok(resp.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Issuer {
+        issued: AtomicUsize,
+        expires_in: u64,
+    }
+
+    async fn issue_token(
+        issuer: web::Data<Issuer>,
+        form: web::Form<Vec<(String, String)>>,
+    ) -> HttpResponse {
+        let has = |key: &str, value: &str| form.iter().any(|(k, v)| k == key && v == value);
+
+        let access_token = if has("grant_type", "client_credentials") {
+            let n = issuer.issued.fetch_add(1, Ordering::SeqCst) + 1;
+            format!("token-{}", n)
+        } else if has("grant_type", "authorization_code") && has("code", "valid-code") {
+            String::from("user-token")
+        } else {
+            return HttpResponse::BadRequest().finish();
+        };
+
+        HttpResponse::Ok().json(serde_json::json!({
+            "access_token": access_token,
+            "expires_in": issuer.expires_in,
+            "token_type": "bearer",
+        }))
+    }
+
+    async fn refuse_token() -> HttpResponse {
+        HttpResponse::Unauthorized().finish()
+    }
+
+    fn token_server(expires_in: u64) -> (test::TestServer, web::Data<Issuer>) {
+        let issuer = web::Data::new(Issuer {
+            issued: AtomicUsize::new(0),
+            expires_in,
+        });
+        let data = issuer.clone();
+        let srv = test::start(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/oauth2/token", web::post().to(issue_token))
+        });
+        (srv, issuer)
+    }
+
+    fn manager(srv: &test::TestServer) -> TokenManager {
+        TokenManager::new(
+            Arc::new(reqwest::Client::new()),
+            &srv.url("/oauth2/token"),
+            "client-id",
+            "client-secret",
+        )
+    }
+
+    #[actix_rt::test]
+    async fn it_caches_the_app_token() {
+        let (srv, issuer) = token_server(3600);
+        let tokens = manager(&srv);
+
+        assert_eq!(tokens.token().await.unwrap(), "token-1");
+        assert_eq!(tokens.token().await.unwrap(), "token-1");
+        assert_eq!(issuer.issued.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_rt::test]
+    async fn it_refreshes_a_token_about_to_expire() {
+        let (srv, issuer) = token_server(60);
+        let tokens = manager(&srv);
+
+        assert_eq!(tokens.token().await.unwrap(), "token-1");
+        assert_eq!(tokens.token().await.unwrap(), "token-2");
+        assert_eq!(issuer.issued.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn it_refreshes_a_rejected_token() {
+        let (srv, _) = token_server(3600);
+        let tokens = manager(&srv);
+
+        let rejected = tokens.token().await.unwrap();
+        tokens.invalidate(&rejected).await;
+        assert_eq!(tokens.token().await.unwrap(), "token-2");
+
+        // a stale rejection does not throw away the replacement
+        tokens.invalidate(&rejected).await;
+        assert_eq!(tokens.token().await.unwrap(), "token-2");
+    }
+
+    #[actix_rt::test]
+    async fn it_exchanges_an_authorization_code() {
+        let (srv, issuer) = token_server(3600);
+        let tokens = manager(&srv);
+
+        let token = tokens.exchange_code("valid-code", "http://localhost/oauth");
+        assert_eq!(token.await.unwrap(), "user-token");
+        assert!(tokens.exchange_code("stale-code", "").await.is_err());
+        assert_eq!(issuer.issued.load(Ordering::SeqCst), 0);
+    }
+
+    #[actix_rt::test]
+    async fn it_fails_when_the_grant_is_refused() {
+        let srv = test::start(|| App::new().route("/oauth2/token", web::post().to(refuse_token)));
+        assert!(manager(&srv).token().await.is_err());
+    }
+}
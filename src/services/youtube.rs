@@ -8,7 +8,7 @@ use url::Url;
 use std::sync::Arc;
 
 use crate::config::CONFIG;
-use crate::service::{validate_schema, Service, ServiceChannel, API};
+use crate::service::{validate_schema, Service, ServiceChannel, ServiceContext, API};
 
 // just using default parts needed for now
 const URL: &str = "https://www.googleapis.com/youtube/v3/videos";
@@ -101,10 +101,12 @@ impl API for Client {
 }
 
 #[async_trait]
-impl Service<Channel> for Client {
-    fn new(client: Arc<reqwest::Client>) -> Client {
+impl Service for Client {
+    type Channel = Channel;
+
+    fn new(ctx: &ServiceContext) -> Client {
         Client {
-            client,
+            client: ctx.client.clone(),
             url: URL.to_string(),
         }
         .with_token(CONFIG.youtube_token.clone())
@@ -193,7 +195,7 @@ impl Service<Channel> for Client {
     }
 
     async fn get_channel_by_name(&self, name: &str) -> anyhow::Result<Channel> {
-        let parts = vec![
+        let parts = [
             "liveStreamingDetails",
             "snippet",
             "statistics",
@@ -215,11 +217,7 @@ impl Service<Channel> for Client {
                 let results: VideosResult = serde_json::from_value(json_resp)?;
                 Ok(results.items[0].clone())
             }
-            Err(e) => Err(anyhow!(
-                "response failed validation: {} {}",
-                json_resp.to_string(),
-                e
-            )),
+            Err(e) => Err(anyhow!("response failed validation: {} {}", json_resp, e)),
         }
     }
 }
@@ -237,7 +235,7 @@ impl ServiceChannel for Channel {
     fn get_thumbnail(&self) -> String {
         self.snippet.thumbnails.medium.url.clone()
     }
-    fn get_viewers(&self) -> u32 {
-        self.statistics.view_count.parse::<u32>().unwrap()
+    fn get_viewers(&self) -> Option<u32> {
+        self.statistics.view_count.parse::<u32>().ok()
     }
 }
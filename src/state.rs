@@ -1,9 +1,32 @@
-use crate::services::{mixer, smashcast, twitch, youtube};
+use std::sync::Arc;
+
+use crate::service::{ServiceContext, ServiceRegistry};
+use crate::services::twitch_auth::TokenManager;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub twitch: twitch::Client,
-    pub mixer: mixer::Client,
-    pub smashcast: smashcast::Client,
-    pub youtube: youtube::Client,
+    pub client: Arc<reqwest::Client>,
+    /// app token shared by every Twitch API and OAuth call
+    pub twitch_tokens: TokenManager,
+    pub services: ServiceRegistry,
+}
+
+impl AppState {
+    pub fn new(client: Arc<reqwest::Client>) -> Self {
+        let twitch_tokens = TokenManager::from_config(client.clone());
+        Self::with_twitch_tokens(client, twitch_tokens)
+    }
+
+    /// State fetching Twitch tokens through the given manager
+    pub fn with_twitch_tokens(client: Arc<reqwest::Client>, twitch_tokens: TokenManager) -> Self {
+        let services = ServiceRegistry::new(&ServiceContext {
+            client: client.clone(),
+            twitch_tokens: twitch_tokens.clone(),
+        });
+        Self {
+            client,
+            twitch_tokens,
+            services,
+        }
+    }
 }
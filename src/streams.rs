@@ -0,0 +1,206 @@
+use actix::Addr;
+use actix_web::{web, Error, HttpResponse};
+
+use std::collections::HashMap;
+
+use crate::database::DbPool;
+use crate::errors::ApiError;
+use crate::models::stream::{self, StreamFilter};
+use crate::registry::{GetAllCounts, LiveStream, StreamRegistry};
+
+/// Page size used when the client does not ask for one
+const DEFAULT_LIMIT: usize = 50;
+/// Largest page a client may ask for
+const MAX_LIMIT: usize = 100;
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("").route(web::get().to(list_streams)));
+}
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    service: Option<String>,
+    live: Option<bool>,
+    nsfw: Option<bool>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct StreamList {
+    /// number of matching streams before pagination
+    total: usize,
+    streams: Vec<LiveStream>,
+}
+
+/// List the public streams, promoted first, then by rustlers and viewers
+async fn list_streams(
+    query: web::Query<ListQuery>,
+    pool: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    let filter = StreamFilter {
+        service: query.service,
+        live: query.live,
+        nsfw: query.nsfw,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as i64;
+    let offset = query.offset.unwrap_or(0) as i64;
+
+    let counts = registry
+        .send(GetAllCounts)
+        .await
+        .map_err(|e| ApiError::Internal(format!("failed to get stream counts: {}", e)))?;
+    let rustlers: HashMap<i64, usize> = counts
+        .iter()
+        .map(|(stream_id, c)| (*stream_id, c.rustlers))
+        .collect();
+
+    let (total, found) = stream::get_public(&pool, &filter, &rustlers, limit, offset)?;
+
+    let streams = found
+        .into_iter()
+        .map(|s| {
+            let counts = s.id.and_then(|id| counts.get(&id)).copied();
+            LiveStream {
+                stream: s,
+                counts: counts.unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(StreamList {
+        total: total as usize,
+        streams,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::setup_pool;
+    use crate::models::stream::Stream;
+    use crate::registry::Join;
+    use crate::routes::routes;
+
+    use actix::Actor;
+    use actix_web::{dev::ServiceResponse, http::StatusCode, test, App};
+    use serde_json::Value;
+    use uuid::Uuid;
+
+    async fn read_json(resp: ServiceResponse) -> Value {
+        serde_json::from_slice(&test::read_body(resp).await).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn it_lists_public_streams() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+
+        let watched = stream::insert(
+            &pool,
+            Stream {
+                service: String::from("twitch"),
+                channel: String::from("jbpratt"),
+                live: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        stream::insert(
+            &pool,
+            Stream {
+                service: String::from("angelthump"),
+                channel: String::from("jbpratt"),
+                promoted: Some(true),
+                nsfw: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        stream::insert(
+            &pool,
+            Stream {
+                service: String::from("youtube"),
+                channel: String::from("idle"),
+                viewers: Some(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        stream::insert(
+            &pool,
+            Stream {
+                service: String::from("smashcast"),
+                channel: String::from("hidden"),
+                hidden: Some(true),
+                viewers: Some(1000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        registry
+            .send(Join {
+                id: Uuid::new_v4(),
+                stream_id: watched.id.unwrap(),
+                afk: false,
+            })
+            .await
+            .unwrap();
+
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        let cases = [
+            ("/api/streams", 3, vec!["angelthump", "twitch", "youtube"]),
+            ("/api/streams?service=twitch", 1, vec!["twitch"]),
+            ("/api/streams?service=smashcast", 0, vec![]),
+            ("/api/streams?live=false", 2, vec!["angelthump", "youtube"]),
+            ("/api/streams?nsfw=false", 2, vec!["twitch", "youtube"]),
+            ("/api/streams?limit=1&offset=1", 3, vec!["twitch"]),
+            ("/api/streams?limit=2&offset=2", 3, vec!["youtube"]),
+        ];
+        for (uri, total, services) in cases.iter() {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+
+            let body = read_json(resp).await;
+            assert_eq!(body["total"], *total, "{}", uri);
+            let found: Vec<&str> = body["streams"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["service"].as_str().unwrap())
+                .collect();
+            assert_eq!(&found, services, "{}", uri);
+        }
+
+        let body = read_json(
+            test::call_service(
+                &mut app,
+                test::TestRequest::get()
+                    .uri("/api/streams?service=twitch")
+                    .to_request(),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(body["streams"][0]["rustlers"], 1);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_invalid_filters() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut app =
+            test::init_service(App::new().data(pool).data(registry).configure(routes)).await;
+
+        for uri in &["/api/streams?live=maybe", "/api/streams?limit=-1"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+    }
+}
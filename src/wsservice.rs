@@ -1,10 +1,21 @@
+use crate::channel::{get_channel_id, valid_stream_path, Channel};
 use crate::database::DbPool;
-use crate::models::stream;
+use crate::errors::ApiError;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::ip_ban::client_ip;
+use crate::models::{banned_streams, stream, user};
+use crate::protocol::{ClientCommand, CommandError, ErrorCode, ServerEvent};
+use crate::registry::{
+    Broadcast, Connect, Disconnect, GetCounts, Join, Kicked, Leave, LiveStream, Moved, SetAfk,
+    StreamRegistry, Terminated,
+};
+use crate::service::ServiceChannel;
+use crate::state::AppState;
 
 use actix::prelude::*;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use anyhow::anyhow;
+use chrono::Utc;
 use uuid::Uuid;
 
 use std::time::{Duration, Instant};
@@ -14,17 +25,53 @@ const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Merge a service channel's metadata into a stream record, keeping the
+/// stored metadata when the channel's is unusable
+fn with_channel(
+    mut stream: stream::Stream,
+    channel: Option<&dyn ServiceChannel>,
+) -> stream::Stream {
+    if let Some(channel) = channel {
+        if let Err(e) = stream::apply_channel(&mut stream, channel) {
+            log::warn!("ignoring metadata for {}: {}", stream.channel, e);
+        }
+    }
+    stream
+}
+
 pub async fn ws_index(
     r: HttpRequest,
     stream: web::Payload,
     data: web::Data<DbPool>,
+    registry: web::Data<Addr<StreamRegistry>>,
+    state: web::Data<AppState>,
+    auth: Result<AuthenticatedUser, ApiError>,
 ) -> Result<HttpResponse, Error> {
+    // anonymous viewers are welcome, banned users are not
+    let user = match auth {
+        Ok(auth) => Some(auth.user),
+        Err(e @ ApiError::Forbidden(_)) => return Err(e.into()),
+        Err(_) => None,
+    };
+
     let conn = data.get_ref();
-    let req = r.connection_info();
-    let ip_addr = req.remote().unwrap_or("");
+    let ip_addr = match client_ip(&r) {
+        Some(ip) => ip.to_string(),
+        None => r.connection_info().remote().unwrap_or("").to_owned(),
+    };
 
     log::info!("{:?}", r);
-    let res = ws::start(WSService::new(conn.clone(), ip_addr), &r, stream);
+    let res = ws::start(
+        WSService::new(
+            conn.clone(),
+            registry.get_ref().clone(),
+            state.get_ref().clone(),
+            &ip_addr,
+            user,
+        ),
+        &r,
+        stream,
+    );
     log::info!("{:?}", res);
     res
 }
@@ -34,20 +81,33 @@ struct WSService {
     id: Uuid,
     hb: Instant,
     db: DbPool,
+    registry: Addr<StreamRegistry>,
+    state: AppState,
     stream_id: Option<i64>,
     afk: bool,
     ip: String,
+    /// the logged in user, `None` for anonymous viewers
+    user: Option<user::User>,
 }
 
 impl WSService {
-    fn new(pool: DbPool, ip_addr: &str) -> Self {
+    fn new(
+        pool: DbPool,
+        registry: Addr<StreamRegistry>,
+        state: AppState,
+        ip_addr: &str,
+        user: Option<user::User>,
+    ) -> Self {
         Self {
             id: Uuid::new_v4(),
             hb: Instant::now(),
             db: pool,
+            registry,
+            state,
             stream_id: None,
             afk: false,
             ip: String::from(ip_addr),
+            user,
         }
     }
 
@@ -72,68 +132,197 @@ impl WSService {
         });
     }
 
+    fn send(&self, ctx: &mut <Self as Actor>::Context, event: ServerEvent) {
+        ctx.text(event.to_text());
+    }
+
+    /// Dispatch a client command to the appropriate handler
+    fn handle_command(
+        &mut self,
+        cmd: ClientCommand,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Result<(), CommandError> {
+        match cmd {
+            ClientCommand::SetStream { channel, service } => {
+                let stream = self.set_stream_to_channel(&channel, &service);
+                self.join_stream(stream, ctx)?;
+            }
+            ClientCommand::SetStreamPath(path) => {
+                let stream = self.set_stream_to_path(&path);
+                self.join_stream(stream, ctx)?;
+            }
+            ClientCommand::ClearStream => self.set_stream(None, ctx),
+            ClientCommand::SetAfk(afk) => self.set_afk(afk, ctx),
+            ClientCommand::GetStream => self.get_stream(ctx)?,
+        }
+        Ok(())
+    }
+
     /// Set the current stream defined for a client
     ///
-    // Clear the current stream defined for the client. Dispatch the command to
-    // the appropriate handler based on the json payload shape.
-    //
-    // If two strings are given treat them as channel and service names.
-    // ex: ["setStream", "angelthump", "dariusirl"]
+    // If a channel and service are given the stream is looked up or created
+    // from them.
+    // ex: ["setStream", "dariusirl", "angelthump"]
     //
     // If one string is given treat it as an overrustle user id.
     // ex: ["setStream", "dariusirl"]
     //
     // If a null literal is given ack without setting a stream
     // ex: ["setStream", null]
-    fn set_stream(&self, input: Vec<String>) {
-        let mut stream_id: u64 = 0;
-        if input.len() == 3 {
-            let channel = input[1].clone();
-            let service = input[2].clone();
-            self.set_stream_to_channel(channel.as_str(), service.as_str(), &stream_id);
-        } else if input.len() == 2 {
+    fn set_stream(&mut self, stream: Option<stream::Stream>, ctx: &mut <Self as Actor>::Context) {
+        self.stream_id = stream.as_ref().and_then(|s| s.id);
+        match self.stream_id {
+            Some(stream_id) => self.registry.do_send(Join {
+                id: self.id,
+                stream_id,
+                afk: self.afk,
+            }),
+            None => self.registry.do_send(Leave { id: self.id }),
         }
+        self.send(ctx, ServerEvent::StreamSet(stream));
+    }
+
+    /// Set a looked up stream, telling the client instead when it is banned
+    fn join_stream(
+        &mut self,
+        stream: Result<stream::Stream, ApiError>,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Result<(), CommandError> {
+        match stream {
+            Ok(stream) => self.set_stream(Some(stream), ctx),
+            Err(ApiError::StreamBanned(reason)) => {
+                self.send(ctx, ServerEvent::StreamBanned(reason))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
     }
 
     fn set_stream_to_channel(
         &self,
         channel: &str,
         service: &str,
-        stream_id: &u64,
-    ) -> anyhow::Result<()> {
-        Ok(())
+    ) -> Result<stream::Stream, ApiError> {
+        let chn = Channel::new(channel.to_string(), service.to_string(), String::new())?;
+        self.upsert_stream(chn, None)
+    }
+
+    fn set_stream_to_path(&self, path: &str) -> Result<stream::Stream, ApiError> {
+        let user = user::get_by_stream_path(&self.db, path)?;
+
+        // users created without a custom path carry the default
+        // "/service/channel" path, which is not a valid stream path
+        let stream_path = if valid_stream_path(user.stream_path.as_str()) {
+            user.stream_path
+        } else {
+            String::new()
+        };
+
+        let chn = Channel::new(user.channel, user.service, stream_path.clone())?;
+        let path = if stream_path.is_empty() {
+            None
+        } else {
+            Some(stream_path)
+        };
+        self.upsert_stream(chn, path)
     }
 
-    fn set_afk(
+    fn upsert_stream(
         &self,
-        input: Vec<String>,
-        ctx: &mut <Self as Actor>::Context,
-    ) -> anyhow::Result<()> {
-        if input.len() == 2 {
-            let afk: bool = input[2].parse().unwrap();
-            // check websocket state for unchanged
-            if afk {
-                // increment stream afk
-            } else {
-                // decrement stream afk
+        chn: Channel,
+        path: Option<String>,
+    ) -> Result<stream::Stream, ApiError> {
+        // streams created before their channel was banned stay in the table
+        if let Some(ban) = banned_streams::find(&self.db, &chn.service, &chn.channel)? {
+            return Err(ApiError::StreamBanned(ban.reason_or_default()));
+        }
+
+        stream::check_collision(&self.db, &chn)?;
+        let id = get_channel_id(&chn) as i64;
+
+        match stream::get_by_id(&self.db, id) {
+            Ok(mut existing) => {
+                existing.updated_at = Utc::now().naive_utc();
+                stream::update(&self.db, existing.clone())?;
+                Ok(existing)
             }
+            Err(ApiError::NotFound(_)) => stream::insert(
+                &self.db,
+                stream::Stream {
+                    service: chn.service,
+                    channel: chn.channel,
+                    path,
+                    ..Default::default()
+                },
+            ),
+            Err(e) => Err(e),
+        }
+    }
 
-            // set stream afk to afk
-            // write out ["AFK_SET", bool]
-            ctx.text("")
-        } else {
-            return Err(anyhow!("invalid command"));
+    /// Set whether the client is afk
+    ///
+    // Setting the same value twice is a no-op for the stream's afk count, but
+    // the client is acked either way.
+    // ex: ["setAfk", true]
+    fn set_afk(&mut self, afk: bool, ctx: &mut <Self as Actor>::Context) {
+        if self.afk != afk {
+            self.afk = afk;
+            if self.stream_id.is_some() {
+                self.registry.do_send(SetAfk { id: self.id, afk });
+            }
         }
 
-        Ok(())
+        self.send(ctx, ServerEvent::AfkSet(afk));
     }
 
-    fn get_ws_stream(&self) -> anyhow::Result<stream::Stream> {
-        if self.stream_id.is_none() {
-            return Err(anyhow!("Invalid stream id"));
+    fn get_ws_stream(&self) -> Result<stream::Stream, CommandError> {
+        match self.stream_id {
+            Some(stream_id) => Ok(stream::get_by_id(&self.db, stream_id)?),
+            None => Err(CommandError::new(ErrorCode::StreamNotSet, "no stream set")),
         }
+    }
+
+    /// Get the full record of the client's current stream
+    ///
+    // The stored stream is merged with its live counts and the latest metadata
+    // from the stream's service, falling back to the stored metadata when the
+    // service has no client or the lookup fails.
+    // ex: ["getStream"]
+    fn get_stream(&self, ctx: &mut <Self as Actor>::Context) -> Result<(), CommandError> {
+        let stream = self.get_ws_stream()?;
+        let stream_id = self.stream_id.unwrap();
+        let registry = self.registry.clone();
+        let state = self.state.clone();
+
+        let fut = async move {
+            let counts = registry
+                .send(GetCounts { stream_id })
+                .await
+                .unwrap_or_default();
 
-        Ok(stream::get_by_id(&self.db, self.stream_id.unwrap())?)
+            let channel = match state
+                .services
+                .get_channel(&stream.service, &stream.channel)
+                .await
+            {
+                Some(Ok(channel)) => Some(channel),
+                Some(Err(e)) => {
+                    log::error!("failed to get channel {}: {}", stream.channel, e);
+                    None
+                }
+                None => None,
+            };
+
+            LiveStream {
+                stream: with_channel(stream, channel.as_deref()),
+                counts,
+            }
+        };
+
+        ctx.spawn(fut.into_actor(self).map(|stream, act, ctx| {
+            act.send(ctx, ServerEvent::StreamGet(stream));
+        }));
+        Ok(())
     }
 }
 
@@ -149,23 +338,20 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WSService {
                 self.hb = Instant::now();
             }
             Ok(ws::Message::Text(text)) => {
-                let res: Vec<String> = serde_json::from_str(text.as_str()).unwrap_or(Vec::new());
-                if res.len() > 0 {
-                    let action = res.first().unwrap();
-                    if action.is_empty() {
-                        return;
-                    }
-
-                    match action.as_str() {
-                        "setAfk" => log::info!("setAfk: {:?}", res),
-                        "setStream" => log::info!("setStream: {:?}", res),
-                        "getStream" => log::info!("getStream: {:?}", res),
-                        _ => log::info!("Unknown: {} {:?}", action, res),
-                    }
+                let res = ClientCommand::parse(&text).and_then(|cmd| self.handle_command(cmd, ctx));
+                if let Err(e) = res {
+                    log::info!("WS: command failed {:?} {:?}", text, e);
+                    self.send(ctx, e.into());
                 }
-                ctx.text(text)
             }
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
+            Ok(ws::Message::Binary(_)) => self.send(
+                ctx,
+                CommandError::new(
+                    ErrorCode::MalformedCommand,
+                    "binary frames are not supported",
+                )
+                .into(),
+            ),
             Ok(ws::Message::Close(_)) => {
                 ctx.stop();
             }
@@ -179,6 +365,484 @@ impl Actor for WSService {
 
     /// Method is called on actor start. We start the heartbeat process here.
     fn started(&mut self, ctx: &mut Self::Context) {
+        match &self.user {
+            Some(user) => log::info!(
+                "websocket session {} connected from {} as {}",
+                self.id,
+                self.ip,
+                user.name
+            ),
+            None => log::info!("websocket session {} connected from {}", self.id, self.ip),
+        }
         self.hb(ctx);
+        self.registry.do_send(Connect {
+            id: self.id,
+            addr: ctx.address().recipient(),
+            kick: ctx.address().recipient(),
+            moved: ctx.address().recipient(),
+            terminate: ctx.address().recipient(),
+            user_id: self.user.as_ref().map(|u| u.id.clone()),
+        });
+    }
+
+    /// Method is called on actor stop, including heartbeat timeouts. The
+    /// session is removed from the registry so it no longer counts as a rustler.
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.registry.do_send(Disconnect { id: self.id });
+    }
+}
+
+impl Handler<Kicked> for WSService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Kicked, ctx: &mut Self::Context) {
+        if self.stream_id != Some(msg.stream_id) {
+            return;
+        }
+        self.stream_id = None;
+        self.send(ctx, ServerEvent::StreamBanned(msg.reason));
+        self.send(ctx, ServerEvent::StreamSet(None));
+    }
+}
+
+impl Handler<Moved> for WSService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Moved, ctx: &mut Self::Context) {
+        if self.stream_id != Some(msg.old_id) {
+            return;
+        }
+        self.stream_id = msg.stream.id;
+        self.send(ctx, ServerEvent::StreamSet(Some(msg.stream)));
+    }
+}
+
+impl Handler<Terminated> for WSService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Terminated, ctx: &mut Self::Context) {
+        self.send(ctx, ServerEvent::SessionEnded(msg.reason.clone()));
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
+    }
+}
+
+impl Handler<Broadcast> for WSService {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, ctx: &mut Self::Context) {
+        ctx.text(msg.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{setup_pool, setup_session};
+    use crate::models::user::Role;
+    use crate::registry::{BanStreams, StreamRekeyed, TerminateUser};
+
+    use actix_web::{http::header, test, App};
+    use futures::{SinkExt, Stream, StreamExt};
+    use serde_json::Value;
+
+    use std::sync::Arc;
+
+    fn test_state() -> AppState {
+        AppState::new(Arc::new(reqwest::Client::new()))
+    }
+
+    fn start_server(pool: DbPool, registry: Addr<StreamRegistry>) -> test::TestServer {
+        test::start(move || {
+            App::new()
+                .data(pool.clone())
+                .data(registry.clone())
+                .data(test_state())
+                .service(web::resource("/ws").route(web::get().to(ws_index)))
+        })
+    }
+
+    /// read frames until the next text frame, skipping pings
+    async fn next_text<S>(framed: &mut S) -> String
+    where
+        S: Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin,
+    {
+        loop {
+            match framed.next().await.unwrap().unwrap() {
+                ws::Frame::Text(text) => return String::from_utf8(text.to_vec()).unwrap(),
+                ws::Frame::Ping(_) => continue,
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_a_stream_to_a_channel() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(pool.clone(), registry, test_state(), "127.0.0.1", None);
+
+        let stream = ws.set_stream_to_channel("jbpratt", "twitch");
+        assert!(stream.is_ok());
+
+        let stream = stream.unwrap();
+        assert_eq!(stream.channel, "jbpratt");
+        assert_eq!(stream.service, "twitch");
+        assert!(stream::get_by_id(&pool, stream.id.unwrap()).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_the_same_channel_twice() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(pool, registry, test_state(), "127.0.0.1", None);
+
+        let first = ws.set_stream_to_channel("jbpratt", "twitch").unwrap();
+        let second = ws.set_stream_to_channel("jbpratt", "twitch").unwrap();
+        assert_eq!(first.id, second.id);
+    }
+
+    #[actix_rt::test]
+    async fn it_fails_to_set_a_stream_to_an_invalid_service() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(pool, registry, test_state(), "127.0.0.1", None);
+        let stream = ws.set_stream_to_channel("jbpratt", "chaturbate");
+        assert!(stream.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_a_stream_to_a_user_path() {
+        let pool = setup_pool();
+        let _ = user::create(
+            &pool,
+            8,
+            Channel {
+                channel: String::from("jbpratt"),
+                service: String::from("twitch"),
+                stream_path: String::from("jbpratt"),
+            },
+            "jbpratt",
+            "0.0.0.0",
+        )
+        .unwrap();
+
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(pool, registry, test_state(), "127.0.0.1", None);
+        let stream = ws.set_stream_to_path("jbpratt").unwrap();
+        assert_eq!(stream.channel, "jbpratt");
+        assert_eq!(stream.path, Some(String::from("jbpratt")));
+    }
+
+    #[actix_rt::test]
+    async fn it_fails_to_set_a_stream_to_an_unknown_user_path() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(pool, registry, test_state(), "127.0.0.1", None);
+        assert!(ws.set_stream_to_path("nobody").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_afk_over_a_websocket() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry.clone());
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+
+        let snapshot = next_text(&mut framed).await;
+        assert_eq!(snapshot, r#"["STREAMS_SET",[]]"#);
+
+        framed
+            .send(ws::Message::Text(
+                r#"["setStream","jbpratt","twitch"]"#.into(),
+            ))
+            .await
+            .unwrap();
+        let stream_set = next_text(&mut framed).await;
+        assert!(stream_set.starts_with(r#"["STREAM_SET",{"#));
+        let stream_set: Value = serde_json::from_str(&stream_set).unwrap();
+        let stream_id = stream_set[1]["id"].as_i64().unwrap();
+
+        for _ in 0..2 {
+            framed
+                .send(ws::Message::Text(r#"["setAfk",true]"#.into()))
+                .await
+                .unwrap();
+            assert_eq!(next_text(&mut framed).await, r#"["AFK_SET",true]"#);
+        }
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.rustlers, 1);
+        assert_eq!(counts.afk, 1);
+
+        framed
+            .send(ws::Message::Text(r#"["setAfk",false]"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut framed).await, r#"["AFK_SET",false]"#);
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.afk, 0);
+    }
+
+    #[actix_rt::test]
+    async fn it_kicks_a_viewer_off_a_banned_stream() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry.clone());
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(
+                r#"["setStream","jbpratt","twitch"]"#.into(),
+            ))
+            .await
+            .unwrap();
+        let stream_set: Value = serde_json::from_str(&next_text(&mut framed).await).unwrap();
+        let stream_id = stream_set[1]["id"].as_i64().unwrap();
+
+        registry.do_send(BanStreams {
+            stream_ids: vec![stream_id],
+            reason: String::from("dmca"),
+        });
+
+        // skip any stream list broadcasts racing the kick
+        let mut frame = next_text(&mut framed).await;
+        while !frame.starts_with(r#"["STREAM_BANNED""#) {
+            frame = next_text(&mut framed).await;
+        }
+        assert_eq!(frame, r#"["STREAM_BANNED","dmca"]"#);
+        assert_eq!(next_text(&mut framed).await, r#"["STREAM_SET",null]"#);
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.rustlers, 0);
+    }
+
+    #[actix_rt::test]
+    async fn it_sets_a_channel_after_its_stream_path_was_moderated() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool.clone(), registry.clone());
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        let set_stream = r#"["setStream","jbpratt","twitch"]"#;
+        framed
+            .send(ws::Message::Text(set_stream.into()))
+            .await
+            .unwrap();
+        let stream_set: Value = serde_json::from_str(&next_text(&mut framed).await).unwrap();
+        let stream_id = stream_set[1]["id"].as_i64().unwrap();
+
+        let changes = stream::StreamModeration {
+            path: Some(Some(String::from("jbpratt_irl"))),
+            ..Default::default()
+        };
+        let moved = stream::moderate(&pool, stream_id, &changes).unwrap();
+        let moved_id = moved.id.unwrap();
+        registry.do_send(StreamRekeyed {
+            old_id: stream_id,
+            stream: moved,
+        });
+
+        // the viewer follows the stream to its new id
+        let mut frame = next_text(&mut framed).await;
+        while !frame.starts_with(r#"["STREAM_SET""#) {
+            frame = next_text(&mut framed).await;
+        }
+        let stream_set: Value = serde_json::from_str(&frame).unwrap();
+        assert_eq!(stream_set[1]["id"], moved_id);
+        assert_eq!(stream_set[1]["path"], "jbpratt_irl");
+
+        framed
+            .send(ws::Message::Text(set_stream.into()))
+            .await
+            .unwrap();
+        let mut frame = next_text(&mut framed).await;
+        while !frame.starts_with(r#"["STREAM_SET""#) {
+            assert!(!frame.starts_with(r#"["ERR""#), "{}", frame);
+            frame = next_text(&mut framed).await;
+        }
+        let stream_set: Value = serde_json::from_str(&frame).unwrap();
+        assert_eq!(stream_set[1]["id"], stream_id);
+        assert_eq!(stream_set[1]["path"], Value::Null);
+
+        let counts = registry.send(GetCounts { stream_id }).await.unwrap();
+        assert_eq!(counts.rustlers, 1);
+        let counts = registry
+            .send(GetCounts {
+                stream_id: moved_id,
+            })
+            .await
+            .unwrap();
+        assert_eq!(counts.rustlers, 0);
+    }
+
+    #[actix_rt::test]
+    async fn it_refuses_to_set_a_banned_stream() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let ws = WSService::new(
+            pool.clone(),
+            registry.clone(),
+            test_state(),
+            "127.0.0.1",
+            None,
+        );
+        // a stream watched before its channel was banned
+        let existing = ws.set_stream_to_channel("jbpratt", "twitch").unwrap();
+
+        let ban = banned_streams::BannedStream {
+            channel: String::from("jbpratt"),
+            service: String::from("twitch"),
+            reason: Some(String::from("dmca")),
+            ..Default::default()
+        };
+        banned_streams::insert(&pool, &ban).unwrap();
+
+        let mut srv = start_server(pool, registry.clone());
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(
+                r#"["setStream","jbpratt","twitch"]"#.into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut framed).await, r#"["STREAM_BANNED","dmca"]"#);
+
+        let counts = registry
+            .send(GetCounts {
+                stream_id: existing.id.unwrap(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(counts.rustlers, 0);
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_an_invalid_set_afk() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry);
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(r#"["setAfk"]"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_text(&mut framed).await,
+            r#"["ERR","MALFORMED_COMMAND","invalid length 1, expected a [\"command\", args...] array"]"#
+        );
+    }
+
+    #[actix_rt::test]
+    async fn it_gets_a_stream_over_a_websocket() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry);
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(
+                r#"["setStream","jbpratt","angelthump"]"#.into(),
+            ))
+            .await
+            .unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(r#"["getStream"]"#.into()))
+            .await
+            .unwrap();
+        let stream_get: Value = serde_json::from_str(&next_text(&mut framed).await).unwrap();
+        assert_eq!(stream_get[0], "STREAM_GET");
+        assert_eq!(stream_get[1]["channel"], "jbpratt");
+        assert_eq!(stream_get[1]["service"], "angelthump");
+        assert_eq!(stream_get[1]["rustlers"], 1);
+        assert_eq!(stream_get[1]["afk_rustlers"], 0);
+        assert_eq!(stream_get[1]["nsfw"], false);
+    }
+
+    #[actix_rt::test]
+    async fn it_fails_to_get_a_stream_when_none_is_set() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry);
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(r#"["getStream"]"#.into()))
+            .await
+            .unwrap();
+        let err: Value = serde_json::from_str(&next_text(&mut framed).await).unwrap();
+        assert_eq!(err[0], "ERR");
+    }
+
+    #[actix_rt::test]
+    async fn it_closes_the_connections_of_a_terminated_user() {
+        let pool = setup_pool();
+        let (u, token) = setup_session(&pool, "rustler", Role::User);
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let srv = start_server(pool, registry.clone());
+
+        let (_, mut framed) = awc::Client::new()
+            .ws(srv.url("/ws"))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .connect()
+            .await
+            .unwrap();
+        let _ = next_text(&mut framed).await;
+
+        let terminated = registry
+            .send(TerminateUser {
+                user_id: u.id.clone(),
+                reason: String::from("banned: spam"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(terminated, 1);
+
+        assert_eq!(
+            next_text(&mut framed).await,
+            r#"["SESSION_ENDED","banned: spam"]"#
+        );
+        match framed.next().await.unwrap().unwrap() {
+            ws::Frame::Close(Some(reason)) => assert_eq!(reason.code, ws::CloseCode::Policy),
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn it_rejects_unknown_commands_without_echoing() {
+        let pool = setup_pool();
+        let registry = StreamRegistry::new(pool.clone()).start();
+        let mut srv = start_server(pool, registry);
+        let mut framed = srv.ws_at("/ws").await.unwrap();
+        let _ = next_text(&mut framed).await;
+
+        framed
+            .send(ws::Message::Text(r#"["setChannel","jbpratt"]"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_text(&mut framed).await,
+            r#"["ERR","UNKNOWN_COMMAND","unknown command: setChannel"]"#
+        );
+
+        framed.send(ws::Message::Binary("[]".into())).await.unwrap();
+        assert_eq!(
+            next_text(&mut framed).await,
+            r#"["ERR","MALFORMED_COMMAND","binary frames are not supported"]"#
+        );
     }
 }